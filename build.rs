@@ -0,0 +1,39 @@
+use std::path::{Path, PathBuf};
+
+//Sources the simulation and the network protocol are built from. Peers whose copies differ could desync, everything else may.
+const PROTOCOL_SOURCES: [&str; 4] = ["src/model", "src/network", "src/pathfinding", "src/sprites"];
+
+/*Exposes a hash of PROTOCOL_SOURCES as ENGINEERS_BUILD_HASH for the network handshake. Unlike the git revision, it
+only changes when those sources do, so builds from different commits can still play together as long as they agree.*/
+fn main() {
+    let mut files = Vec::new();
+    for dir in PROTOCOL_SOURCES.iter() {
+        collect_files(Path::new(dir), &mut files);
+        println!("cargo:rerun-if-changed={}", dir);
+    }
+    files.sort();
+
+    //FNV-1a, so the hash is the same on every platform and compiler version.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for file in files {
+        let path = file.to_string_lossy().replace('\\', "/");
+        //Line endings depend on the checkout, not on the code.
+        let contents: Vec<u8> = std::fs::read(&file).unwrap().into_iter().filter(|byte| *byte != b'\r').collect();
+        for byte in path.bytes().chain(std::iter::once(0)).chain(contents) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    println!("cargo:rustc-env=ENGINEERS_BUILD_HASH={:016x}", hash);
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+/*Startup configuration. On native builds this is read from environment variables and command-line arguments
+(arguments win), on the web build it is read from the page URL query string, e.g. index.html?host=example.com&room=42
+Without a room, the game opens the room browser to pick or create one.*/
+use crate::model::gamemanager::DEFAULT_ROLLBACK_WINDOW;
+use crate::network::handshake::MAX_PLAYERS;
+use crate::network::latency::MAX_INPUT_DELAY;
+use crate::tiledmap;
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 3012;
+pub const DEFAULT_PLAYER_NAME: &str = "Engineer";
+pub const DEFAULT_PLAYERS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportKind {
+    WebSocket, //Through a room on the relay server.
+    Tcp, //Straight to one other player at host:port, without the relay server. Native only.
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    pub host: String,
+    pub port: u16,
+    pub room_id: Option<String>,
+    pub player_name: String,
+    pub offline: bool, //Play locally against a loopback instead of connecting to the relay server.
+    pub transport: TransportKind,
+    pub record_path: Option<String>, //Record a replay of the match to this file.
+    pub replay_path: Option<String>, //Play back this replay file instead of joining a match.
+    pub spectator: bool, //Join the match to watch it without controlling any units.
+    pub rollback_window: u32, //In ticks. A longer window survives worse lag before resyncing, at the cost of memory.
+    pub players: usize, //The lobby counts down once this many players, including us, are ready.
+    pub map_name: String, //Played offline, and proposed in the lobby if we end up the host.
+    pub server: bool, //Run the headless authoritative server for the room instead of playing. Native only.
+    pub authoritative: bool, //Play against an authoritative server in the room rather than in lockstep.
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_PORT,
+            room_id: None,
+            player_name: DEFAULT_PLAYER_NAME.to_string(),
+            offline: false,
+            transport: TransportKind::WebSocket,
+            record_path: None,
+            replay_path: None,
+            spectator: false,
+            rollback_window: DEFAULT_ROLLBACK_WINDOW,
+            players: DEFAULT_PLAYERS,
+            map_name: tiledmap::DEFAULT_MAP.to_string(),
+            server: false,
+            authoritative: false,
+        }
+    }
+}
+
+impl GameConfig {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        let mut config = GameConfig::default();
+        config.apply_env(|key| std::env::var(key).ok());
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        config.apply_pairs(parse_args(&args));
+        config
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        let mut config = GameConfig::default();
+        config.apply_pairs(parse_query_string(&web::query_string()));
+        config
+    }
+
+    //The relay itself, outside of any room.
+    pub fn server_url(&self) -> String {
+        format!("ws://{}:{}/", self.host, self.port)
+    }
+
+    pub fn room_url(&self, room_id: &str) -> String {
+        format!("{}{}", self.server_url(), room_id)
+    }
+
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, get_env: F) {
+        let mut pairs = Vec::new();
+        for (variable, key) in [
+            ("ENGINEERS_HOST", "host"),
+            ("ENGINEERS_PORT", "port"),
+            ("ENGINEERS_ROOM", "room"),
+            ("ENGINEERS_NAME", "name"),
+            ("ENGINEERS_OFFLINE", "offline"),
+            ("ENGINEERS_TRANSPORT", "transport"),
+            ("ENGINEERS_RECORD", "record"),
+            ("ENGINEERS_REPLAY", "replay"),
+            ("ENGINEERS_SPECTATE", "spectate"),
+            ("ENGINEERS_ROLLBACK_WINDOW", "rollback-window"),
+            ("ENGINEERS_PLAYERS", "players"),
+            ("ENGINEERS_MAP", "map"),
+            ("ENGINEERS_SERVER", "server"),
+            ("ENGINEERS_AUTHORITATIVE", "authoritative"),
+        ] {
+            if let Some(value) = get_env(variable) {
+                pairs.push((key.to_string(), value));
+            }
+        }
+        self.apply_pairs(pairs);
+    }
+
+    pub fn apply_pairs(&mut self, pairs: Vec<(String, String)>) {
+        for (key, value) in pairs {
+            match key.as_str() {
+                "host" => self.host = value,
+                "port" => match value.parse::<u16>() {
+                    Ok(port) => self.port = port,
+                    Err(_) => println!("Ignoring invalid port: {}", value),
+                },
+                "room" if value.is_empty() => self.room_id = None,
+                "room" => self.room_id = Some(value),
+                "name" => self.player_name = value,
+                "offline" => self.offline = parse_flag(&value),
+                "transport" => match value.as_str() {
+                    "websocket" => self.transport = TransportKind::WebSocket,
+                    "tcp" => self.transport = TransportKind::Tcp,
+                    _ => println!("Ignoring unknown transport: {}", value),
+                },
+                "record" if value.is_empty() => println!("Ignoring record option without a file name"),
+                "record" => self.record_path = Some(value),
+                "replay" if value.is_empty() => println!("Ignoring replay option without a file name"),
+                "replay" => self.replay_path = Some(value),
+                "spectate" => self.spectator = parse_flag(&value),
+                //Requests are scheduled up to MAX_INPUT_DELAY ticks ahead, a shorter window would resync on every late one.
+                "rollback-window" => match value.parse::<u32>() {
+                    Ok(window) if window > MAX_INPUT_DELAY => self.rollback_window = window,
+                    _ => println!("Ignoring invalid rollback window: {}", value),
+                },
+                "players" => match value.parse::<usize>() {
+                    Ok(players) if (2..=MAX_PLAYERS).contains(&players) => self.players = players,
+                    _ => println!("Ignoring invalid number of players: {}", value),
+                },
+                "map" if tiledmap::get_map_names().contains(&value) => self.map_name = value,
+                "map" => println!("Ignoring unknown map: {}", value),
+                "server" => self.server = parse_flag(&value),
+                "authoritative" => self.authoritative = parse_flag(&value),
+                _ => println!("Ignoring unknown option: {}", key),
+            }
+        }
+    }
+}
+
+//Accepts "--key=value", "--key value" and bare flags such as "--offline".
+pub fn parse_args(args: &[String]) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let option = match arg.strip_prefix("--") {
+            Some(option) => option,
+            None => {
+                println!("Ignoring argument: {}", arg);
+                continue;
+            }
+        };
+        match option.split_once('=') {
+            Some((key, value)) => pairs.push((key.to_string(), value.to_string())),
+            None => {
+                let value = iter.next_if(|next| !next.starts_with("--")).cloned().unwrap_or_default();
+                pairs.push((option.to_string(), value));
+            }
+        }
+    }
+    pairs
+}
+
+pub fn parse_query_string(query: &str) -> Vec<(String, String)> {
+    query
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (decode_query_component(key), decode_query_component(value)),
+            None => (decode_query_component(pair), String::new()),
+        })
+        .collect()
+}
+
+//A flag given without a value ("--offline" or "?offline") counts as set.
+fn parse_flag(value: &str) -> bool {
+    !matches!(value, "0" | "false" | "no" | "off")
+}
+
+fn decode_query_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            //Exactly two hex digits, from_str_radix alone would also take a sign like "%+f".
+            b'%' if i + 2 < bytes.len() && bytes[i + 1..i + 3].iter().all(|byte| byte.is_ascii_hexdigit()) => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use sapp_jsutils::JsObject;
+
+    extern "C" {
+        //Provided by the engineers_config plugin registered in web/index.html.
+        fn engineers_query_string() -> JsObject;
+    }
+
+    pub fn query_string() -> String {
+        let mut query = String::new();
+        unsafe { engineers_query_string() }.to_string(&mut query);
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_test() {
+        let config = GameConfig::default();
+        assert_eq!(config.room_id, None);
+        assert_eq!(config.server_url(), "ws://127.0.0.1:3012/");
+        assert_eq!(config.room_url("12345"), "ws://127.0.0.1:3012/12345");
+    }
+
+    #[test]
+    fn args_override_env_test() {
+        let mut config = GameConfig::default();
+        config.apply_env(|key| match key {
+            "ENGINEERS_HOST" => Some("10.0.0.2".to_string()),
+            "ENGINEERS_PORT" => Some("4000".to_string()),
+            _ => None,
+        });
+        let args: Vec<String> = vec!["--port=5000", "--room", "lobby", "--name=Paul"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        config.apply_pairs(parse_args(&args));
+        assert_eq!(config.host, "10.0.0.2");
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.room_id.as_deref(), Some("lobby"));
+        assert_eq!(config.player_name, "Paul");
+        assert_eq!(config.room_url("lobby"), "ws://10.0.0.2:5000/lobby");
+    }
+
+    #[test]
+    fn invalid_port_is_ignored_test() {
+        let mut config = GameConfig::default();
+        config.apply_pairs(vec![("port".to_string(), "not_a_port".to_string())]);
+        assert_eq!(config.port, DEFAULT_PORT);
+    }
+
+    #[test]
+    fn query_string_test() {
+        let mut config = GameConfig::default();
+        config.apply_pairs(parse_query_string("?host=example.com&port=8080&room=99&name=Paul%20B"));
+        assert_eq!(config.room_id.as_deref(), Some("99"));
+        assert_eq!(config.server_url(), "ws://example.com:8080/");
+        assert_eq!(config.player_name, "Paul B");
+        assert!(!config.offline);
+    }
+
+    #[test]
+    fn invalid_percent_escapes_are_kept_test() {
+        //The + is then a space, as anywhere else.
+        assert_eq!(decode_query_component("100%+f"), "100% f");
+        assert_eq!(decode_query_component("%zz%4"), "%zz%4");
+        assert_eq!(decode_query_component("%4a%4B"), "JK");
+    }
+
+    #[test]
+    fn offline_flag_test() {
+        let mut config = GameConfig::default();
+        let args: Vec<String> = vec!["--offline", "--name", "Paul"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        config.apply_pairs(parse_args(&args));
+        assert!(config.offline);
+        assert_eq!(config.player_name, "Paul");
+        assert_eq!(config.record_path, None);
+
+        config.apply_pairs(parse_query_string("offline=false"));
+        assert!(!config.offline);
+        config.apply_pairs(parse_query_string("?offline"));
+        assert!(config.offline);
+        assert!(!config.spectator);
+        config.apply_pairs(parse_query_string("?spectate&room=7"));
+        assert!(config.spectator);
+    }
+
+    #[test]
+    fn record_option_test() {
+        let mut config = GameConfig::default();
+        config.apply_env(|key| match key {
+            "ENGINEERS_RECORD" => Some("env.replay".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.record_path, Some("env.replay".to_string()));
+        let args: Vec<String> = vec!["--record", "match.replay", "--offline"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        config.apply_pairs(parse_args(&args));
+        assert_eq!(config.record_path, Some("match.replay".to_string()));
+        config.apply_pairs(parse_args(&["--record".to_string()]));
+        assert_eq!(config.record_path, Some("match.replay".to_string()));
+        config.apply_pairs(parse_args(&["--replay=match.replay".to_string()]));
+        assert_eq!(config.replay_path, Some("match.replay".to_string()));
+    }
+
+    #[test]
+    fn rollback_window_option_test() {
+        let mut config = GameConfig::default();
+        assert_eq!(config.rollback_window, DEFAULT_ROLLBACK_WINDOW);
+        config.apply_env(|key| match key {
+            "ENGINEERS_ROLLBACK_WINDOW" => Some("120".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.rollback_window, 120);
+        config.apply_pairs(parse_args(&["--rollback-window=5".to_string()]));
+        assert_eq!(config.rollback_window, 120);
+        config.apply_pairs(parse_query_string("rollback-window=abc"));
+        assert_eq!(config.rollback_window, 120);
+        config.apply_pairs(parse_query_string("rollback-window=60"));
+        assert_eq!(config.rollback_window, 60);
+    }
+
+    #[test]
+    fn players_option_test() {
+        let mut config = GameConfig::default();
+        assert_eq!(config.players, DEFAULT_PLAYERS);
+        config.apply_pairs(parse_args(&["--players".to_string(), "4".to_string()]));
+        assert_eq!(config.players, 4);
+        config.apply_pairs(parse_query_string("players=1"));
+        assert_eq!(config.players, 4);
+        config.apply_pairs(parse_query_string("players=99"));
+        assert_eq!(config.players, 4);
+    }
+
+    #[test]
+    fn map_option_test() {
+        let mut config = GameConfig::default();
+        assert_eq!(config.map_name, tiledmap::DEFAULT_MAP);
+        config.apply_pairs(parse_args(&["--map=missing.json".to_string()]));
+        assert_eq!(config.map_name, tiledmap::DEFAULT_MAP);
+        config.apply_pairs(parse_query_string("map=tiledmap.json"));
+        assert_eq!(config.map_name, "tiledmap.json");
+    }
+
+    #[test]
+    fn server_options_test() {
+        let mut config = GameConfig::default();
+        assert_eq!((config.server, config.authoritative), (false, false));
+        config.apply_env(|key| match key {
+            "ENGINEERS_SERVER" => Some("1".to_string()),
+            _ => None,
+        });
+        assert!(config.server);
+        config.apply_pairs(parse_args(&["--authoritative".to_string(), "--server=0".to_string()]));
+        assert_eq!((config.server, config.authoritative), (false, true));
+    }
+
+    #[test]
+    fn transport_option_test() {
+        let mut config = GameConfig::default();
+        assert_eq!(config.transport, TransportKind::WebSocket);
+        config.apply_env(|key| match key {
+            "ENGINEERS_TRANSPORT" => Some("tcp".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.transport, TransportKind::Tcp);
+        config.apply_pairs(parse_args(&["--transport=carrier-pigeon".to_string()]));
+        assert_eq!(config.transport, TransportKind::Tcp);
+        config.apply_pairs(parse_args(&["--transport".to_string(), "websocket".to_string()]));
+        assert_eq!(config.transport, TransportKind::WebSocket);
+    }
+}
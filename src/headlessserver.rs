@@ -0,0 +1,91 @@
+/*Headless authoritative server, started with --server. It joins the room like a player would and waits for the
+players, then simulates the match from their requests and broadcasts its state, which the players are corrected to.
+It never issues requests and has no window, so it keeps time with std rather than macroquad.*/
+use crate::config::GameConfig;
+use crate::model::gamemanager::{GameManager, TICK_DURATION};
+use crate::model::gamestate::GameState;
+use crate::model::random::SimulationRng;
+use crate::network::handshake::{Handshake, HandshakeState, Hello, PeerRole};
+use crate::network::reconnectingtransport::ReconnectingTransport;
+use crate::network::transport::{Transport, TransportEvent};
+use crate::network::websockettransport::WebSocketTransport;
+use crate::pathfinding::pathfinder::Pathfinder;
+use crate::tiledmap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub fn run(game_config: &GameConfig) {
+    //There is no room browser or lobby here, the players pick the map in the room's lobby and it has to match ours.
+    let room_id = match &game_config.room_id {
+        Some(room_id) => room_id.clone(),
+        None => {
+            eprintln!("The server needs a room, pass --room");
+            std::process::exit(1);
+        }
+    };
+    let (tilemap_struct, map_id) = tiledmap::load_map(&game_config.map_name).unwrap();
+
+    let start_time = Instant::now();
+    let get_time = move || start_time.elapsed().as_secs_f64();
+    //Only picks our player id, the server does not contribute to the match seed.
+    let mut rng = SimulationRng::new(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64);
+
+    let room_url = game_config.room_url(&room_id);
+    println!("Serving {} on {} for {} players", game_config.map_name, room_url, game_config.players);
+    let mut transport: Box<dyn Transport> = Box::new(ReconnectingTransport::new(
+        Box::new(move || WebSocketTransport::connect(room_url.as_str()).map(|socket| Box::new(socket) as Box<dyn Transport>)),
+        Box::new(get_time),
+    ));
+    while !transport.is_connected() {
+        if let Some(TransportEvent::Error(e)) = transport.poll_event() {
+            println!("{}", e);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut local_hello = Hello::new(map_id, rng.next_u32().max(1), game_config.player_name.clone(), 0);
+    local_hello.role = PeerRole::Server;
+    let mut handshake = Handshake::new(local_hello.clone(), game_config.players);
+    let remote_hellos = loop {
+        match handshake.update(transport.as_mut(), get_time()) {
+            HandshakeState::Pending => {}
+            HandshakeState::Complete(remote_hellos) => break remote_hellos.clone(),
+            HandshakeState::Rejected(rejection) => {
+                eprintln!("Cannot serve game: {}", rejection);
+                std::process::exit(1);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    let match_seed = local_hello.agree_match_seed(&remote_hellos);
+    println!("Match seed {:016x}", match_seed);
+
+    let sprite_map = tiledmap::get_tilemap_spritelist(&tilemap_struct);
+    let sprite_uuid_list: Vec<u32> = sprite_map.keys().cloned().collect();
+    let game_state = GameState{sprite_map, sprite_uuid_list, selected_entity: 0, rng: SimulationRng::new(match_seed)};
+    let mut game_manager = GameManager::new(game_state, Pathfinder::new(tilemap_struct), Some(transport));
+    game_manager.local_player_id = local_hello.player_id;
+    game_manager.is_server = true;
+    game_manager.rollback_window = game_config.rollback_window;
+    game_manager.peers.extend(remote_hellos.iter().cloned());
+    game_manager.player_slots = local_hello.player_slots(&remote_hellos);
+    game_manager.local_hello = Some(local_hello);
+    for message in std::mem::take(&mut handshake.pending_messages) {
+        game_manager.receive_message(&message);
+    }
+
+    /*Same tick loop as the players, minus the rendering*/
+    let mut last_tick_time = get_time();
+    let mut tick_count = 0;
+    game_manager.current_game_state.process_tick(tick_count);
+    loop {
+        let current_time = get_time();
+        game_manager.getNetworkRequests();
+        while current_time - last_tick_time >= TICK_DURATION {
+            tick_count += 1;
+            game_manager.process_tick(tick_count);
+            last_tick_time += TICK_DURATION;
+        }
+        game_manager.flush_outgoing_messages();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -1,9 +1,12 @@
 use crate::model::gamestate::GameState;
-use crate::model::gamemanager::GameManager;
+use crate::model::gamemanager::{GameManager, TICK_DURATION};
+use crate::model::idallocator::IdAllocator;
+use crate::model::random::SimulationRng;
+use crate::model::replay::{Replay, ReplayHeader, ReplayRecorder};
+use crate::model::replayplayer::{ReplayPlayer, MAX_SPEED, MIN_SPEED};
 use include_dir::include_dir;
 use include_dir::Dir;
 use macroquad::prelude::*;
-use quad_net::quad_socket::client::QuadSocket;
 
 mod pathfinding {
     pub mod pathfinder;
@@ -17,40 +20,194 @@ mod sprites {
     pub mod mechsprite;
     pub mod tilesprite;
 }
+mod view {
+    pub mod engineerview;
+    pub mod gameview;
+    pub mod mechview;
+    pub mod tileview;
+}
+mod network {
+    pub mod handshake;
+    pub mod latency;
+    pub mod lobby;
+    pub mod loopbacktransport;
+    pub mod reconnectingtransport;
+    pub mod simulatedtransport;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod tcptransport;
+    pub mod transport;
+    pub mod websockettransport;
+    pub mod wireformat;
+}
 mod model {
+    pub mod checksum;
+    pub mod idallocator;
+    pub mod inputtracker;
+    pub mod messages;
+    pub mod random;
+    pub mod replay;
+    pub mod replayplayer;
     pub mod requests;
+    pub mod snapshot;
     pub mod gamemanager;
     pub mod gamestate;
 }
 pub mod tiledmap;
+pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headlessserver;
 use pathfinding::pathfinder::{Pathfinder, TilePosition};
-use quad_net::web_socket::WebSocket;
+use network::loopbacktransport::LoopbackTransport;
+use network::reconnectingtransport::ReconnectingTransport;
+use network::simulatedtransport::{NetworkConditions, SimulatedTransport};
+use network::handshake::{Handshake, HandshakeState, Hello, PeerRole, MAX_PLAYERS};
+use network::lobby::{Lobby, LobbyResult, LobbyState, RoomBrowser};
+use network::transport::{Transport, TransportEvent};
+use network::websockettransport::WebSocketTransport;
+#[cfg(not(target_arch = "wasm32"))]
+use network::tcptransport::TcpTransport;
 use sprites::sprite::{world_to_grid_coords, SpriteID};
 use model::requests::*;
-#[macroquad::main("engineers")]
-async fn main() {
-    static ASSETS_DIR: Dir = include_dir!("assets");
+use view::gameview::{GameView, SLOT_COLOURS};
+fn main() {
+    let game_config = config::GameConfig::load();
+    #[cfg(not(target_arch = "wasm32"))]
+    if game_config.server {
+        headlessserver::run(&game_config);
+        return;
+    }
+    macroquad::Window::new("engineers", play(game_config));
+}
+
+async fn play(game_config: config::GameConfig) {
     static MUSIC_DIR: Dir = include_dir!("assets/music");
     /*Load Assets*/
-    let tileset_png = ASSETS_DIR.get_file("tileset.png").unwrap();
     let background_music_ogg = MUSIC_DIR.get_file("background_music.ogg").unwrap();
-    let tileset = Texture2D::from_file_with_format(tileset_png.contents(), None);
+    let mut game_view: GameView = GameView::new();
     let background_music = load_sound_from_bytes(background_music_ogg.contents())
         .await
         .unwrap();
 
-    /*Load JSON Tilemap*/
-    let lib_rs = ASSETS_DIR.get_file("tiledmap.json").unwrap();
-    let body = lib_rs.contents_utf8().unwrap();
-    let tilemap_struct: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
-    /****************************/
-
-    /*Seed random number generator*/
+    /*Seed random number generator, used for our player id and match seed contribution*/
     rand::srand((get_time() * 99999.99) as u64);
     /**************************/
 
+    #[cfg(target_arch = "wasm32")]
+    if game_config.server {
+        println!("Ignoring server option on web");
+    }
+    if let Some(replay_path) = &game_config.replay_path {
+        play_replay(replay_path, game_view).await;
+        return;
+    }
+    let player_id = rand::rand().max(1);
+    let mut map_name = game_config.map_name.clone();
+    let mut players = game_config.players;
+    let mut lobby_result: Option<LobbyResult> = None;
+    let mut lobby_messages = Vec::new();
+    let mut transport: Box<dyn Transport>;
+    if game_config.offline {
+        /*Offline mode: requests are echoed back in-process, no relay server required */
+        println!("Starting offline as {}", game_config.player_name);
+        transport = Box::new(LoopbackTransport::new());
+    } else {
+        let room_id;
+        if game_config.transport == config::TransportKind::Tcp {
+            room_id = format!("{}:{}", game_config.host, game_config.port);
+            if players != 2 {
+                println!("Ignoring players option, a direct connection is between two players");
+                players = 2;
+            }
+            transport = match connect_tcp(&game_config).await {
+                Ok(transport) => transport,
+                Err(e) => {
+                    show_error(&e).await;
+                    return;
+                }
+            };
+        } else {
+            room_id = match &game_config.room_id {
+                Some(room_id) => room_id.clone(),
+                None => choose_room(&game_config).await,
+            };
+            /*initialize web socket connection to the room, reconnecting whenever it drops */
+            let room_url = game_config.room_url(&room_id);
+            println!("Connecting to {} as {}", room_url, game_config.player_name);
+            transport = Box::new(ReconnectingTransport::new(
+                Box::new(move || WebSocketTransport::connect(room_url.as_str()).map(|socket| Box::new(socket) as Box<dyn Transport>)),
+                Box::new(get_time),
+            ));
+
+            {
+                while !transport.is_connected() {
+                    if let Some(TransportEvent::Error(e)) = transport.poll_event() {
+                        println!("{}", e);
+                    }
+                    next_frame().await;
+                }
+            }
+        }
+
+        /*Pick a slot and the map, then count down to the first tick together. Spectators skip the lobby */
+        if !game_config.spectator {
+            let mut lobby = Lobby::new(player_id, game_config.player_name.clone(), map_name.clone(), players);
+            let result = run_lobby(&mut lobby, transport.as_mut(), &room_id).await;
+            map_name = result.map_name.clone();
+            players = result.members.len();
+            lobby_messages = std::mem::take(&mut lobby.pending_messages);
+            lobby_result = Some(result);
+        }
+    }
+    let (tilemap_struct, map_id) = match tiledmap::load_map(&map_name) {
+        Some(map) => map,
+        None => {
+            show_error(&format!("Cannot load map {}", map_name)).await;
+            return;
+        }
+    };
+
+    /*Exchange hellos with the other players, refusing to play against an incompatible build or map */
+    let mut local_hello = Hello::new(map_id, player_id, game_config.player_name.clone(), ((rand::rand() as u64) << 32) | rand::rand() as u64);
+    if game_config.spectator && game_config.offline {
+        println!("Ignoring spectate option in offline mode");
+    } else if game_config.spectator {
+        local_hello.role = PeerRole::Spectator;
+    }
+    local_hello.slot = lobby_result.as_ref().map(|lobby_result| lobby_result.slot);
+    let mut handshake = Handshake::new(local_hello.clone(), players);
+    handshake.expects_server = game_config.authoritative && !game_config.offline;
+    if game_config.offline {
+        handshake.state = HandshakeState::Complete(Vec::new());
+    }
+    for message in lobby_messages {
+        handshake.handle_message(transport.as_mut(), message);
+    }
+    let remote_hellos = loop {
+        match handshake.update(transport.as_mut(), get_time()) {
+            HandshakeState::Pending => {
+                println!("Waiting on Data...");
+            }
+            HandshakeState::Complete(remote_hellos) => break remote_hellos.clone(),
+            HandshakeState::Rejected(rejection) => {
+                show_error(&format!("Cannot join game: {}", rejection)).await;
+                return;
+            }
+        }
+        next_frame().await;
+    };
+    let handshake_time = get_time();
+    if let Some(lobby_result) = &lobby_result {
+        println!("Handshake completed {:.2}s after the countdown", handshake_time - lobby_result.start_time);
+    }
+    let match_seed = local_hello.agree_match_seed(&remote_hellos);
+    //The lobby hands out slots, without one they go by player id.
+    let player_slot = local_hello.player_slot(&remote_hellos);
+    println!("Match seed {:016x}, playing in slot {}", match_seed, player_slot);
+    //Anything random from here on is reproducible from the match seed.
+    rand::srand(match_seed.wrapping_add(player_slot as u64));
+
     /*Generate Tiled Sprite List*/
-    let sprite_map_store: std::collections::HashMap<u32,SpriteID> = tiledmap::get_tilemap_spritelist(tileset, &tilemap_struct);
+    let sprite_map_store: std::collections::HashMap<u32,SpriteID> = tiledmap::get_tilemap_spritelist(&tilemap_struct);
     let mut render_list: Vec<u32> = Vec::new();
     for (uuid, _sprite) in sprite_map_store.iter()
     {
@@ -58,53 +215,63 @@ async fn main() {
     }
 
     /*Create Game State*/
-    let game_state: GameState = GameState{sprite_map:sprite_map_store, sprite_uuid_list:render_list, selected_entity: 0};
+    let game_state: GameState = GameState{sprite_map:sprite_map_store, sprite_uuid_list:render_list, selected_entity: 0, rng: SimulationRng::new(match_seed)};
 
-    /*initialize web socket connection to server */
-    #[cfg(not(target_arch = "wasm32"))]
-    let mut socket = WebSocket::connect("ws://127.0.0.1:3012/12345").unwrap();
-    #[cfg(target_arch = "wasm32")]
-    let mut socket = WebSocket::connect("ws://127.0.0.1:3012/12345").unwrap();
-
-    {
-        while socket.connected() == false {
-            next_frame().await;
+    /*Create Game Manager. Game traffic goes through the network simulator, which is a no-op until set up with F4 */
+    let simulated_transport = SimulatedTransport::new(transport, Box::new(get_time), rand::rand() as u64);
+    let network_conditions = simulated_transport.conditions();
+    let mut game_manager: GameManager = GameManager::new(game_state, Pathfinder::new(tilemap_struct), Some(Box::new(simulated_transport)));
+    game_manager.local_player_id = local_hello.player_id;
+    game_manager.id_allocator = IdAllocator::for_player_slot(player_slot);
+    game_manager.rollback_window = game_config.rollback_window;
+    game_manager.player_slots = local_hello.player_slots(&remote_hellos);
+    if let Some(record_path) = &game_config.record_path {
+        match ReplayRecorder::create(record_path, &ReplayHeader::new(local_hello.map_id.clone(), match_seed, game_manager.player_slots.clone())) {
+            Ok(replay_recorder) => {
+                println!("Recording replay to {}", record_path);
+                game_manager.replay_recorder = Some(replay_recorder);
+            }
+            Err(e) => println!("Not recording replay: {}", e),
         }
     }
-    let mut connected=false;
-    while(!connected)
-    {
-        println!("Waiting on Data...");
-
-        while let Some(event) =  socket.try_recv(){
-            println!("Received {:?}", event);
-            connected=true;
-        }
-        socket.send_text(("1"));
-        next_frame().await;
+    game_manager.peers.extend(remote_hellos.iter().cloned());
+    game_manager.authority = remote_hellos.iter().find(|hello| hello.role == PeerRole::Server).map(|hello| hello.player_id);
+    if let Some(server_id) = game_manager.authority {
+        println!("Playing against server {}", server_id);
+    }
+    let is_spectator = local_hello.role == PeerRole::Spectator;
+    game_manager.local_hello = Some(local_hello);
+    for message in std::mem::take(&mut handshake.pending_messages) {
+        game_manager.receive_message(&message);
+    }
+    if is_spectator {
+        println!("Spectating {}", remote_hellos[0].player_name);
+        game_manager.join_as_spectator();
     }
-
-    /*Create Game Manager*/
-    let mut game_manager: GameManager = GameManager{socket: socket, requests: RequestQueue::default(), game_state_history: std::collections::HashMap::new(), current_game_state: game_state, last_tick: 0, pathfinder:  Pathfinder::new(tilemap_struct)};
  
     /*Initialize Game State By executing first tick - 0 */
-    let mut last_tick_time: f64 = get_time();
+    /*Tick 0 is when the handshake completed, which can be long after the lobby's countdown, e.g. while waiting for the
+    server. The countdown started every player's handshake together, so they complete it within a relay round trip.*/
+    let mut last_tick_time: f64 = handshake_time;
     let mut tick_count =0;
     game_manager.current_game_state.process_tick(tick_count);
        
-    /*Generate Random Create Requests for Engineers */
-    for _i in 0..3 {
-        let position = vec2(rand::gen_range::<f32>(1.0, 10.0) as f32, rand::gen_range::<f32>(1.0, 10.0) as f32);
-        let uuid: u32 = rand::rand();
-        let request: Request = Request::SpriteCreate(SpriteCreateRequest {tick:40 - position.x as u32, sprite_uuid: uuid, position: TilePosition{x: position.x as i32,y: position.y as i32}, sprite_type: SpriteType::Engineer});
-        game_manager.addLocalRequest(request);
-    }
-    /*Generate Random Create Requests for Mechs */
-    for _i in 0..3 {
-        let position = vec2(rand::gen_range::<f32>(1.0, 10.0) as f32, rand::gen_range::<f32>(1.0, 10.0) as f32);
-        let uuid: u32 = rand::rand();
-        let request: Request = Request::SpriteCreate(SpriteCreateRequest {tick:40 - position.x as u32, sprite_uuid: uuid, position: TilePosition{x: position.x as i32,y: position.y as i32}, sprite_type: SpriteType::Mech});
-        game_manager.addLocalRequest(request);
+    /*Spectators only watch, so they do not create any units*/
+    if !is_spectator {
+        /*Generate Random Create Requests for Engineers */
+        for _i in 0..3 {
+            let position = vec2(rand::gen_range::<f32>(1.0, 10.0) as f32, rand::gen_range::<f32>(1.0, 10.0) as f32);
+            let uuid: u32 = game_manager.allocate_unit_id();
+            let request: Request = Request::SpriteCreate(SpriteCreateRequest {tick:40 - position.x as u32, player_id: game_manager.local_player_id, sequence: game_manager.next_request_sequence(), sprite_uuid: uuid, position: TilePosition{x: position.x as i32,y: position.y as i32}, sprite_type: SpriteType::Engineer});
+            game_manager.addLocalRequest(request);
+        }
+        /*Generate Random Create Requests for Mechs */
+        for _i in 0..3 {
+            let position = vec2(rand::gen_range::<f32>(1.0, 10.0) as f32, rand::gen_range::<f32>(1.0, 10.0) as f32);
+            let uuid: u32 = game_manager.allocate_unit_id();
+            let request: Request = Request::SpriteCreate(SpriteCreateRequest {tick:40 - position.x as u32, player_id: game_manager.local_player_id, sequence: game_manager.next_request_sequence(), sprite_uuid: uuid, position: TilePosition{x: position.x as i32,y: position.y as i32}, sprite_type: SpriteType::Mech});
+            game_manager.addLocalRequest(request);
+        }
     }
 
     /*Play Music */
@@ -117,6 +284,8 @@ async fn main() {
     );
 
     /* Render/Tick Loop */
+    let mut show_network_overlay = false;
+    let mut show_network_simulator = false;
     loop {
         clear_background(BLACK);
         let camera = Camera2D {
@@ -131,11 +300,8 @@ async fn main() {
 
         /* Mouse/Touch Handling */
         let (mouse_x, mouse_y) = mouse_position();
-        let mut world_vec: Vec2 = vec2(-1., -1.);
-        let mut grid_coords: Vec2 = vec2(-1., -1.);
         if is_mouse_button_released(MouseButton::Left) {
-            world_vec = camera.screen_to_world(vec2(mouse_x, mouse_y));
-            grid_coords = world_to_grid_coords(world_vec);
+            let world_vec = camera.screen_to_world(vec2(mouse_x, mouse_y));
             game_manager.mouse_clicked(world_vec);
             println!("World X: {}", world_vec.x);
             println!("World Y: {}", world_vec.y);
@@ -146,19 +312,332 @@ async fn main() {
         
         /*Get current game clock time*/
         let current_time = get_time();
+        game_manager.update_latency(current_time);
         game_manager.getNetworkRequests();
-        while(current_time-last_tick_time>=0.05)
+        if game_manager.last_tick > tick_count {
+            //A resync snapshot moved the simulation ahead of the local clock.
+            tick_count = game_manager.last_tick;
+        }
+        while current_time-last_tick_time>=TICK_DURATION
         {
-            tick_count=tick_count+1;
+            tick_count += 1;
             game_manager.process_tick(tick_count);
-            last_tick_time=last_tick_time+0.05;
+            last_tick_time += TICK_DURATION;
+        }
+        game_manager.flush_outgoing_messages();
+        game_view.render(&game_manager.current_game_state);
+        set_default_camera();
+        if is_spectator {
+            draw_text("Spectating", 20.0, 30.0, 24.0, WHITE);
+        }
+        if game_manager.connection_lost {
+            draw_text("Connection lost, reconnecting...", 20.0, 60.0, 24.0, RED);
+        }
+        if is_key_pressed(KeyCode::F3) {
+            show_network_overlay = !show_network_overlay;
+        }
+        if show_network_overlay {
+            draw_network_overlay(&game_manager);
+        }
+        if is_key_pressed(KeyCode::F4) {
+            show_network_simulator = !show_network_simulator;
+        }
+        if show_network_simulator {
+            update_network_simulator(&network_conditions);
+        }
+
+        next_frame().await;
+    }
+}
+
+//Never returns, the message stays up until the window is closed.
+/*Connects straight to the other player at host:port over TCP, or if nobody is listening there yet, listens on the port
+until they connect. Unlike the relay connection, a dropped one is not reconnected.*/
+#[cfg(not(target_arch = "wasm32"))]
+async fn connect_tcp(game_config: &config::GameConfig) -> Result<Box<dyn Transport>, String> {
+    let address = format!("{}:{}", game_config.host, game_config.port);
+    if let Ok(transport) = TcpTransport::connect(address.as_str()) {
+        println!("Connected to {} as {}", address, game_config.player_name);
+        return Ok(Box::new(transport));
+    }
+    let listener = std::net::TcpListener::bind(("0.0.0.0", game_config.port)).map_err(|e| format!("Cannot listen on port {}: {}", game_config.port, e))?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    println!("Waiting for the other player on port {}", game_config.port);
+    loop {
+        match listener.accept() {
+            Ok((stream, _address)) => return Ok(Box::new(TcpTransport::from_stream(stream)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(format!("Cannot accept the other player: {}", e)),
+        }
+        clear_background(BLACK);
+        draw_text(&format!("Waiting for the other player on port {}", game_config.port), 20.0, 40.0, 30.0, WHITE);
+        next_frame().await;
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn connect_tcp(_game_config: &config::GameConfig) -> Result<Box<dyn Transport>, String> {
+    Err("Direct TCP connections are not available on the web".to_string())
+}
+
+async fn show_error(message: &str) {
+    loop {
+        clear_background(BLACK);
+        draw_text(message, 20.0, 40.0, 30.0, WHITE);
+        next_frame().await;
+    }
+}
+
+/*Room browser, shown when no room was given: Up/Down select a room, Enter joins it and N creates a new one.*/
+async fn choose_room(game_config: &config::GameConfig) -> String {
+    let server_url = game_config.server_url();
+    println!("Listing rooms on {}", server_url);
+    let mut transport = ReconnectingTransport::new(
+        Box::new(move || WebSocketTransport::connect(server_url.as_str()).map(|socket| Box::new(socket) as Box<dyn Transport>)),
+        Box::new(get_time),
+    );
+    let mut room_browser = RoomBrowser::default();
+    let mut selected: usize = 0;
+    loop {
+        room_browser.update(&mut transport, get_time());
+        let rooms = &room_browser.rooms;
+        if is_key_pressed(KeyCode::Down) {
+            selected += 1;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            selected = selected.saturating_sub(1);
+        }
+        selected = selected.min(rooms.len().saturating_sub(1));
+        if is_key_pressed(KeyCode::Enter) && !rooms.is_empty() {
+            return rooms[selected].room_id.clone();
+        }
+        if is_key_pressed(KeyCode::N) {
+            return rand::gen_range::<u32>(10000, 100000).to_string();
+        }
+
+        clear_background(BLACK);
+        let mut lines = vec![
+            format!("Rooms on {}:{}", game_config.host, game_config.port),
+            "[Up/Down] select  [Enter] join  [N] new room".to_string(),
+        ];
+        if !transport.is_connected() {
+            lines.push("Connecting...".to_string());
+        } else if rooms.is_empty() {
+            lines.push("No rooms yet, press N to create one".to_string());
+        }
+        for (i, room) in rooms.iter().enumerate() {
+            lines.push(format!(
+                "{} {}  {} players{}",
+                if i == selected { ">" } else { " " },
+                room.room_id,
+                room.members,
+                if room.playing { "  (playing)" } else { "" }
+            ));
+        }
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 20.0, 40.0 + 30.0 * i as f32, 30.0, WHITE);
+        }
+        next_frame().await;
+    }
+}
+
+/*Lobby: Left/Right pick a slot, M changes the map (host only) and Space toggles ready.*/
+async fn run_lobby(lobby: &mut Lobby, transport: &mut dyn Transport, room_id: &str) -> LobbyResult {
+    let map_names = tiledmap::get_map_names();
+    loop {
+        let countdown = match lobby.update(transport, get_time()) {
+            LobbyState::Starting(lobby_result) => return lobby_result.clone(),
+            LobbyState::Countdown(seconds_left) => Some(*seconds_left),
+            LobbyState::Waiting => None,
+        };
+        for (key, step) in [(KeyCode::Left, MAX_PLAYERS - 1), (KeyCode::Right, 1)] {
+            if is_key_pressed(key) {
+                //The next free slot in that direction.
+                let current_slot = lobby.local.slot as usize;
+                (1..MAX_PLAYERS).map(|i| ((current_slot + step * i) % MAX_PLAYERS) as u8).any(|slot| lobby.choose_slot(slot));
+            }
+        }
+        if is_key_pressed(KeyCode::M) {
+            let next_map = map_names.iter().position(|map_name| *map_name == lobby.local.map_name).map_or(0, |i| (i + 1) % map_names.len());
+            lobby.choose_map(map_names[next_map].clone());
+        }
+        if is_key_pressed(KeyCode::Space) {
+            let ready = !lobby.local.ready;
+            lobby.set_ready(ready);
+        }
+
+        clear_background(BLACK);
+        let members = lobby.members();
+        let status = match countdown {
+            Some(seconds_left) => format!("Starting in {:.0}", seconds_left.ceil()),
+            None if members.len() < lobby.min_players => format!("Waiting for players ({}/{})", members.len(), lobby.min_players),
+            None => "Waiting for everyone to be ready".to_string(),
+        };
+        let header = [
+            format!("Room {}  map {}", room_id, lobby.map_name()),
+            "[Left/Right] slot  [M] map (host)  [Space] ready".to_string(),
+            status,
+        ];
+        for (i, line) in header.iter().enumerate() {
+            draw_text(line, 20.0, 40.0 + 30.0 * i as f32, 30.0, WHITE);
+        }
+        for (i, member) in members.iter().enumerate() {
+            let y = 150.0 + 30.0 * i as f32;
+            draw_rectangle(20.0, y - 18.0, 20.0, 20.0, SLOT_COLOURS[member.slot as usize % MAX_PLAYERS]);
+            let you = if member.player_id == lobby.local.player_id { " (you)" } else { "" };
+            let ready = if member.ready { "ready" } else { "not ready" };
+            draw_text(&format!("Slot {}  {}{}  {}", member.slot + 1, member.player_name, you, ready), 50.0, y, 30.0, WHITE);
+        }
+        next_frame().await;
+    }
+}
+
+/*Replay playback: Space pauses, Up/Down double or halve the speed, Left/Right seek 5 seconds, Home restarts.
+The replay is played on the map it was recorded on, whatever map was configured.*/
+async fn play_replay(replay_path: &str, mut game_view: GameView) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let replay = Replay::load(replay_path);
+    #[cfg(target_arch = "wasm32")]
+    let replay: Result<Replay, String> = Err(format!("Cannot play {}: replays are not supported on web", replay_path));
+    let replay = match replay {
+        Ok(replay) => replay,
+        Err(e) => return show_error(&e).await,
+    };
+    let tilemap_struct = match tiledmap::load_map_by_id(&replay.header.map_id) {
+        Some(tilemap_struct) => tilemap_struct,
+        None => return show_error(&format!("Cannot play {}: map {} is not available", replay_path, replay.header.map_id)).await,
+    };
+    if replay.header.build_hash != network::handshake::BUILD_HASH {
+        println!("Replay was recorded with build {}, it may not play back faithfully", replay.header.build_hash);
+    }
+
+    let sprite_map_store: std::collections::HashMap<u32,SpriteID> = tiledmap::get_tilemap_spritelist(&tilemap_struct);
+    let render_list: Vec<u32> = sprite_map_store.keys().cloned().collect();
+    let game_state: GameState = GameState{sprite_map:sprite_map_store, sprite_uuid_list:render_list, selected_entity: 0, rng: SimulationRng::new(replay.header.match_seed)};
+    let mut game_manager: GameManager = GameManager::new(game_state.clone(), Pathfinder::new(tilemap_struct), None);
+    game_manager.player_slots = replay.header.player_slots.clone();
+    let mut replay_player = ReplayPlayer::new(replay, game_state);
+    let seek_ticks = 100;
+
+    loop {
+        clear_background(BLACK);
+        let camera = Camera2D {
+            zoom: vec2(
+                1. / macroquad::window::screen_width() * 2.,
+                -1. / macroquad::window::screen_height() * 2.,
+            ),
+            target: vec2(0.0, 100.0),
+            ..Default::default()
+        };
+        set_camera(&camera);
+
+        if is_key_pressed(KeyCode::Space) {
+            replay_player.paused = !replay_player.paused;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            replay_player.set_speed(replay_player.speed * 2.0);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            replay_player.set_speed(replay_player.speed / 2.0);
+        }
+        let current_tick = game_manager.last_tick;
+        if is_key_pressed(KeyCode::Right) {
+            replay_player.seek(&mut game_manager, current_tick + seek_ticks);
+        }
+        if is_key_pressed(KeyCode::Left) {
+            replay_player.seek(&mut game_manager, current_tick.saturating_sub(seek_ticks));
+        }
+        if is_key_pressed(KeyCode::Home) {
+            replay_player.seek(&mut game_manager, 0);
         }
-        game_manager.render();
+        replay_player.update(&mut game_manager, get_frame_time() as f64);
+        game_view.render(&game_manager.current_game_state);
+
+        set_default_camera();
+        let status = format!(
+            "Replay tick {} / {}  speed {}x (x{}-x{}){}",
+            game_manager.last_tick,
+            replay_player.last_request_tick(),
+            replay_player.speed,
+            MIN_SPEED,
+            MAX_SPEED,
+            if replay_player.paused { "  PAUSED" } else { "" }
+        );
+        draw_text(&status, 20.0, 30.0, 24.0, WHITE);
 
         next_frame().await;
     }
 }
 
+//Toggled with F3.
+fn draw_network_overlay(game_manager: &GameManager) {
+    let mut lines = vec![format!(
+        "Tick {} (confirmed {})  input delay {} ticks  {} requests queued",
+        game_manager.last_tick,
+        game_manager.confirmed_tick(),
+        game_manager.input_delay(),
+        game_manager.requests.GetNumberOfRequests()
+    )];
+    for peer in &game_manager.peers {
+        match game_manager.latency.get_peer_latency(peer.player_id) {
+            Some(latency) => lines.push(format!(
+                "{} ({}): rtt {:.0} ms (last {:.0} ms, variance {:.0} ms)",
+                peer.player_name,
+                peer.player_id,
+                latency.smoothed_rtt * 1000.0,
+                latency.last_rtt * 1000.0,
+                latency.rtt_variance * 1000.0
+            )),
+            None => lines.push(format!("{} ({}): no round trip measured yet", peer.player_name, peer.player_id)),
+        }
+    }
+    for (player_id, confirmed_tick) in game_manager.input_tracker.get_all_confirmed_ticks() {
+        lines.push(format!("Player {}: inputs confirmed up to tick {}", player_id, confirmed_tick));
+    }
+    if game_manager.desync_detector.is_desynchronized() {
+        lines.push("Desynchronized from a peer, see the log for the first differing tick".to_string());
+    }
+    if let Some(server_id) = game_manager.authority {
+        lines.push(format!("Server {}: corrected our state {} times", server_id, game_manager.corrections));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, 20.0, 60.0 + 20.0 * i as f32, 20.0, YELLOW);
+    }
+}
+
+/*F4 network simulator: 1/2 change the latency, 3/4 the jitter, 5/6 the reordering and 7/8 the loss, 0 resets.*/
+fn update_network_simulator(network_conditions: &std::cell::Cell<NetworkConditions>) {
+    fn adjust(value: &mut f64, down: KeyCode, up: KeyCode, step: f64, max: f64) {
+        if is_key_pressed(down) {
+            *value = (*value - step).max(0.0);
+        }
+        if is_key_pressed(up) {
+            *value = (*value + step).min(max);
+        }
+    }
+    let mut conditions = network_conditions.get();
+    adjust(&mut conditions.latency, KeyCode::Key1, KeyCode::Key2, 0.025, 2.0);
+    adjust(&mut conditions.jitter, KeyCode::Key3, KeyCode::Key4, 0.01, 1.0);
+    adjust(&mut conditions.reorder_chance, KeyCode::Key5, KeyCode::Key6, 0.05, 1.0);
+    adjust(&mut conditions.loss_chance, KeyCode::Key7, KeyCode::Key8, 0.01, 1.0);
+    if is_key_pressed(KeyCode::Key0) {
+        conditions = NetworkConditions::default();
+    }
+    network_conditions.set(conditions);
+
+    let lines = [
+        "Network simulator (each direction)".to_string(),
+        format!("[1/2] latency {:.0} ms", conditions.latency * 1000.0),
+        format!("[3/4] jitter {:.0} ms", conditions.jitter * 1000.0),
+        format!("[5/6] reorder {:.0}%", conditions.reorder_chance * 100.0),
+        format!("[7/8] loss {:.0}%", conditions.loss_chance * 100.0),
+        "[0] reset".to_string(),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, screen_width() - 320.0, 30.0 + 20.0 * i as f32, 20.0, ORANGE);
+    }
+}
+
 pub fn aspect_ratio() -> f32 {
     macroquad::window::screen_width() / macroquad::window::screen_height()
 }
@@ -171,10 +650,10 @@ mod tests {
         static ASSETS_DIR: Dir = include_dir!("assets");
         let lib_rs = ASSETS_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
-        assert_eq!(pathfinder.tile_is_walkable(0, 0), false);
+        assert!(!pathfinder.tile_is_walkable(0, 0));
     }
 
     #[test]
@@ -182,10 +661,10 @@ mod tests {
         static ASSETS_DIR: Dir = include_dir!("assets");
         let lib_rs = ASSETS_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
-        assert_eq!(pathfinder.tile_is_walkable(1, 2), true);
+        assert!(pathfinder.tile_is_walkable(1, 2));
     }
 
     #[test]
@@ -193,9 +672,9 @@ mod tests {
         static ASSETS_DIR: Dir = include_dir!("assets");
         let lib_rs = ASSETS_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
-        assert_eq!(pathfinder.tile_is_walkable(20, 1), false);
+        assert!(!pathfinder.tile_is_walkable(20, 1));
     }
 }
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use nanoserde::{SerBin, DeBin, DeBinErr};
+use crate::network::wireformat::de_bin_vec;
+use std::collections::BTreeMap;
+
+type Tick = u32;
+
+/*FNV-1a. Used instead of std's DefaultHasher because its output is not guaranteed to be stable between
+Rust versions or platforms, and both peers (native and wasm) must agree on every bit.*/
+pub struct StateHasher {
+    state: u64,
+}
+
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub fn new() -> Self {
+        StateHasher { state: StateHasher::OFFSET_BASIS }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(StateHasher::PRIME);
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.write_bytes(&[value]);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+//Checksum of the simulation state after a tick, plus a checksum per unit so a mismatch can be narrowed down.
+#[derive(Serialize, Deserialize, SerBin, Debug, Clone, PartialEq)]
+pub struct StateChecksum {
+    pub tick: Tick,
+    pub checksum: u64,
+    pub unit_checksums: Vec<(u32, u64)>, //Sorted by unit uuid.
+}
+
+//Not derived, so a remote peer cannot make us allocate an arbitrary number of unit checksums, see de_bin_vec.
+impl DeBin for StateChecksum {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(StateChecksum { tick: DeBin::de_bin(o, d)?, checksum: DeBin::de_bin(o, d)?, unit_checksums: de_bin_vec(o, d)? })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesyncReport {
+    pub tick: Tick,
+    pub local_checksum: u64,
+    pub remote_checksum: u64,
+    pub differing_units: Vec<u32>,
+}
+
+impl std::fmt::Display for DesyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Desync detected at tick {}: local checksum {:016x}, remote checksum {:016x}, differing units {:?}",
+            self.tick, self.local_checksum, self.remote_checksum, self.differing_units
+        )
+    }
+}
+
+impl DesyncReport {
+    pub fn from_checksums(local: &StateChecksum, remote: &StateChecksum) -> Self {
+        let local_units: BTreeMap<u32, u64> = local.unit_checksums.iter().cloned().collect();
+        let remote_units: BTreeMap<u32, u64> = remote.unit_checksums.iter().cloned().collect();
+        let mut differing_units: Vec<u32> = Vec::new();
+        for (uuid, checksum) in &local_units {
+            if remote_units.get(uuid) != Some(checksum) {
+                differing_units.push(*uuid);
+            }
+        }
+        for uuid in remote_units.keys() {
+            if !local_units.contains_key(uuid) {
+                differing_units.push(*uuid);
+            }
+        }
+        differing_units.sort();
+        DesyncReport {
+            tick: local.tick,
+            local_checksum: local.checksum,
+            remote_checksum: remote.checksum,
+            differing_units,
+        }
+    }
+}
+
+/*Pairs up our checksums with the ones reported by every remote peer for the same tick. Either side may arrive first,
+so our checksums are kept until they are too old for any peer to still report, and remote ones until ours arrives.
+Only the earliest mismatch is kept, since everything after the first divergent tick is expected to differ too.*/
+#[derive(Default)]
+pub struct DesyncDetector {
+    local: BTreeMap<Tick, StateChecksum>,
+    remote: BTreeMap<Tick, Vec<StateChecksum>>,
+    pub first_desync: Option<DesyncReport>,
+}
+
+impl DesyncDetector {
+    const MAX_PENDING: usize = 100;
+
+    //Returns the report if this checksum revealed the first desync.
+    pub fn add_local(&mut self, checksum: StateChecksum) -> Option<DesyncReport> {
+        let tick = checksum.tick;
+        DesyncDetector::insert_bounded(&mut self.local, tick, checksum);
+        let mut new_report = None;
+        for remote in self.remote.remove(&tick).unwrap_or_default() {
+            new_report = self.compare(&remote).or(new_report);
+        }
+        new_report
+    }
+
+    pub fn add_remote(&mut self, checksum: StateChecksum) -> Option<DesyncReport> {
+        if self.local.contains_key(&checksum.tick) {
+            return self.compare(&checksum);
+        }
+        let mut pending = self.remote.remove(&checksum.tick).unwrap_or_default();
+        pending.push(checksum.clone());
+        DesyncDetector::insert_bounded(&mut self.remote, checksum.tick, pending);
+        None
+    }
+
+    pub fn is_desynchronized(&self) -> bool {
+        self.first_desync.is_some()
+    }
+
+    fn insert_bounded<T>(checksums: &mut BTreeMap<Tick, T>, tick: Tick, value: T) {
+        checksums.insert(tick, value);
+        while checksums.len() > DesyncDetector::MAX_PENDING {
+            let oldest = *checksums.keys().next().unwrap();
+            checksums.remove(&oldest);
+        }
+    }
+
+    fn compare(&mut self, remote: &StateChecksum) -> Option<DesyncReport> {
+        let local = self.local.get(&remote.tick)?;
+        if local.checksum == remote.checksum {
+            return None;
+        }
+        let report = DesyncReport::from_checksums(local, remote);
+        let is_earliest = match &self.first_desync {
+            Some(first) => report.tick < first.tick,
+            None => true,
+        };
+        if !is_earliest {
+            return None;
+        }
+        self.first_desync = Some(report.clone());
+        Some(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(tick: Tick, units: Vec<(u32, u64)>) -> StateChecksum {
+        let mut hasher = StateHasher::new();
+        for (uuid, unit_checksum) in &units {
+            hasher.write_u32(*uuid);
+            hasher.write_u64(*unit_checksum);
+        }
+        StateChecksum { tick, checksum: hasher.finish(), unit_checksums: units }
+    }
+
+    #[test]
+    fn hasher_is_stable_test() {
+        let mut hasher = StateHasher::new();
+        assert_eq!(hasher.finish(), 0xcbf29ce484222325);
+        hasher.write_bytes(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn matching_checksums_test() {
+        let mut detector = DesyncDetector::default();
+        assert_eq!(detector.add_local(checksum(10, vec![(1, 5), (2, 6)])), None);
+        assert_eq!(detector.add_remote(checksum(10, vec![(1, 5), (2, 6)])), None);
+        assert!(!detector.is_desynchronized());
+    }
+
+    #[test]
+    fn first_divergent_tick_is_reported_test() {
+        let mut detector = DesyncDetector::default();
+        detector.add_remote(checksum(30, vec![(1, 5), (2, 9)]));
+        detector.add_remote(checksum(20, vec![(1, 5), (2, 7), (3, 1)]));
+        let report = detector.add_local(checksum(30, vec![(1, 5), (2, 6)])).unwrap();
+        assert_eq!(report.tick, 30);
+        let report = detector.add_local(checksum(20, vec![(1, 5), (2, 6)])).unwrap();
+        assert_eq!(report.tick, 20);
+        assert_eq!(report.differing_units, vec![2, 3]);
+        assert_eq!(detector.add_local(checksum(40, vec![])), None);
+        assert_eq!(detector.add_remote(checksum(40, vec![(4, 4)])), None);
+        assert_eq!(detector.first_desync.unwrap().tick, 20);
+    }
+
+    #[test]
+    fn every_remote_peer_is_compared_test() {
+        let mut detector = DesyncDetector::default();
+        assert_eq!(detector.add_remote(checksum(10, vec![(1, 5)])), None);
+        assert_eq!(detector.add_local(checksum(10, vec![(1, 5)])), None);
+        assert_eq!(detector.add_remote(checksum(10, vec![(1, 5)])), None);
+        //The third peer's checksum arrives after the other two matched ours.
+        let report = detector.add_remote(checksum(10, vec![(1, 6)])).unwrap();
+        assert_eq!(report.differing_units, vec![1]);
+    }
+}
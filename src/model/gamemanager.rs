@@ -1,7 +1,13 @@
-use super::super::sprites::sprite::{grid_to_world_coords, world_to_grid_coords, Sprite, SpriteID};
-use super::requests::Request;
+use super::super::sprites::sprite::{world_to_grid_coords, Sprite, SpriteID};
+use super::requests::{PlayerID, Request, RequestError, RequestID};
 use super::requests::RequestQueue;
-use super::gamestate::GameState;
+use super::gamestate::{GameState, SimulationState};
+use super::checksum::DesyncDetector;
+use super::idallocator::IdAllocator;
+use super::inputtracker::{InputProgress, InputTracker};
+use super::messages::{NetworkMessage, ReceivedRequests, RetransmitRequest};
+use super::replay::ReplayRecorder;
+use super::snapshot::GameStateSnapshot;
 use crate::model::requests::RequestImpl;
 use crate::Pathfinder;
 use crate::SpriteMoveRequest;
@@ -9,87 +15,381 @@ use crate::TilePosition;
 use crate::Vec2;
 use crate::sprites::engineersprite::Engineer;
 use crate::sprites::mechsprite::Mech;
-use include_dir::include_dir;
-use macroquad::texture::Texture2D;
-use quad_net::web_socket::WebSocket;
-use serde::{Serialize, Deserialize};
+use crate::network::handshake::{HandshakeMessage, Hello, PeerRole};
+use crate::network::latency::{LatencyTracker, Pong, MIN_INPUT_DELAY};
+use crate::network::transport::{Transport, TransportEvent};
+use crate::network::wireformat;
 
 type Tick = u32;
 
+pub const TICK_DURATION: f64 = 0.05; //Seconds of wall clock per simulation tick.
+
+const CHECKSUM_INTERVAL: Tick = 10; //Ticks between checksum exchanges.
+pub const DEFAULT_ROLLBACK_WINDOW: Tick = 45;
+//History kept for a peer that has not confirmed its inputs, 30 seconds. A peer further behind has to resync.
+const MAX_UNCONFIRMED_HISTORY: Tick = 600;
+const AUTHORITATIVE_STATE_INTERVAL: Tick = 20; //Ticks between the server's state broadcasts.
+
 pub enum RequestStatus {
     Synchronized,
     Desynchronized,
 }
 pub struct GameManager {
     pub requests: RequestQueue, //Requests are global and maintain as much history as game_state_history.
-    pub game_state_history: std::collections::HashMap<Tick, GameState>,
+    pub game_state_history: std::collections::HashMap<Tick, SimulationState>, //Units and rng only, tiles never change.
     pub current_game_state: GameState,
     pub last_tick: u32,
     pub pathfinder: Pathfinder,
-    pub socket: WebSocket
+    pub transport: Option<Box<dyn Transport>>, //None when running headless (tests, dedicated server).
+    pub desync_detector: DesyncDetector,
+    pub awaiting_resync: bool,
+    pub local_hello: Option<Hello>, //Set once the handshake has completed, used to reject incompatible peers that connect later.
+    pub outgoing_messages: Vec<NetworkMessage>, //Batched into a single frame by flush_outgoing_messages.
+    pub local_player_id: PlayerID, //Issuer of requests made through this client.
+    pub id_allocator: IdAllocator, //Uuids for units created by this client, see IdAllocator.
+    pub replay_recorder: Option<ReplayRecorder>, //Every queued request is recorded when set.
+    pub is_spectator: bool, //Spectators render the match but never issue requests or select units.
+    pub peers: Vec<Hello>, //Peers that have completed the handshake with us.
+    pub player_slots: std::collections::BTreeMap<PlayerID, u8>, //Whose id block each player creates units from, empty when unknown.
+    pub latency: LatencyTracker,
+    pub rollback_window: Tick, //History kept even for confirmed ticks, how late a request may arrive and still be rolled back to.
+    pub request_sequence: u32, //Sequence number of the last request issued by this client.
+    pub requested_retransmissions: std::collections::BTreeSet<RequestID>, //Missing requests we have already asked for.
+    pub connection_lost: bool, //Set from a disconnect until the transport has reconnected.
+    pub input_tracker: InputTracker, //Which ticks each remote player has sent all its requests for.
+    pub last_announced_tick: Tick, //Last tick we sent our InputProgress for.
+    pub last_checksum_tick: Tick, //Newest confirmed tick the checksums have been exchanged up to.
+    pub is_server: bool, //Headless authoritative server, its state is broadcast to the players and never replaced.
+    pub authority: Option<PlayerID>, //The server of a server-authoritative match, whose state overrides ours.
+    pub corrections: u32, //Times the server's state differed from ours and replaced it.
 }
 
 impl GameManager {
+    pub fn new(game_state: GameState, pathfinder: Pathfinder, transport: Option<Box<dyn Transport>>) -> Self {
+        //The state before the first tick, so requests for tick 1 can be rolled back to like any other.
+        let mut game_state_history = std::collections::HashMap::new();
+        game_state_history.insert(0, game_state.capture_simulation_state());
+        GameManager{transport, requests: RequestQueue::default(), game_state_history, current_game_state: game_state, last_tick: 0, pathfinder, desync_detector: DesyncDetector::default(), awaiting_resync: false, local_hello: None, outgoing_messages: Vec::new(), local_player_id: 0, id_allocator: IdAllocator::for_player_slot(0), replay_recorder: None, is_spectator: false, peers: Vec::new(), player_slots: std::collections::BTreeMap::new(), latency: LatencyTracker::default(), rollback_window: DEFAULT_ROLLBACK_WINDOW, request_sequence: 0, requested_retransmissions: std::collections::BTreeSet::new(), connection_lost: false, input_tracker: InputTracker::default(), last_announced_tick: 0, last_checksum_tick: 0, is_server: false, authority: None, corrections: 0}
+    }
+
     pub fn process_tick(&mut self, tick: u32) {
         let mut local_tick = tick;
-        if (local_tick > self.last_tick) {
-            self.last_tick = local_tick;
-            self.process_tick_work(local_tick);
+        if local_tick > self.last_tick {
+            //Process any ticks skipped over, e.g. after loading a snapshot from behind the local clock.
+            while self.last_tick < tick {
+                local_tick = self.last_tick + 1;
+                self.last_tick = local_tick;
+                self.process_tick_work(local_tick);
+                self.exchange_checksums();
+                self.broadcast_authoritative_state(local_tick);
+            }
         } else {
-            self.current_game_state = (*self.game_state_history.get(&tick).unwrap()).clone();
-            while (local_tick <= self.last_tick) {
-                self.process_tick_work(tick);
-                local_tick = local_tick + 1;
+            self.rollback(tick);
+        }
+    }
+
+    /*A request for `tick` arrived after that tick was simulated. Restores the state from before `tick` and simulates
+    every tick from there up to last_tick again in order, so each tick's requests are applied exactly once.*/
+    fn rollback(&mut self, tick: Tick) {
+        if tick == 0 {
+            return; //Tick 0 is the initial state, no requests run on it.
+        }
+        match self.game_state_history.get(&(tick - 1)) {
+            Some(simulation_state) => self.current_game_state.restore_simulation_state(simulation_state),
+            None => {
+                //Older than the history, e.g. from before a snapshot we loaded.
+                self.request_resync();
+                return;
             }
         }
+        for local_tick in tick..=self.last_tick {
+            self.process_tick_work(local_tick);
+        }
     }
     pub fn process_tick_work(&mut self, tick: u32) {
         let requests_to_be_processed = self.requests.GetRequestsOfParticularTick(tick);
         /*Process Requests Here*/
         for request in requests_to_be_processed {
-            self.process_request(&request);
+            if let Err(e) = self.process_request(&request) {
+                println!("Rejected request at tick {} from player {}: {}", tick, request.get_player_id(), e);
+            }
         }
         /***********************/
         self.current_game_state.process_tick(tick);
         self.game_state_history
-            .insert(tick, self.current_game_state.clone());
-        if (tick > 47)
-        {
-            self.game_state_history.remove(&(tick - 47));
+            .insert(tick, self.current_game_state.capture_simulation_state());
+        let oldest_kept_tick = self.oldest_kept_tick(tick);
+        self.game_state_history.retain(|history_tick, _| *history_tick >= oldest_kept_tick);
+        //Rolling back restores the state from before a tick, so the requests of the oldest kept tick are not needed either.
+        self.requests.PurgeRequestsOlderThanTick(oldest_kept_tick + 1);
+    }
+
+    //Ticks of state kept in game_state_history, and of requests kept in the queue.
+    fn history_length(&self) -> Tick {
+        self.rollback_window + 2
+    }
+
+    /*The oldest tick whose state is kept after simulating `tick`: the last history_length ticks, and further back
+    while some peer has not confirmed them, since a request for those ticks can still arrive.*/
+    fn oldest_kept_tick(&self, tick: Tick) -> Tick {
+        let oldest_in_window = (tick + 1).saturating_sub(self.history_length());
+        let oldest_unconfirmed = (tick + 1).saturating_sub(self.history_length().max(MAX_UNCONFIRMED_HISTORY));
+        self.confirmed_tick().clamp(oldest_unconfirmed, oldest_in_window)
+    }
+
+    //The newest tick every player has sent all its requests for. Ticks after it are speculative and may be rolled back.
+    pub fn confirmed_tick(&self) -> Tick {
+        //A player that joined but has not announced anything yet has not confirmed any tick.
+        let unannounced = self.peers.iter().any(|peer| {
+            peer.role == PeerRole::Player && peer.player_id != self.local_player_id && self.input_tracker.get_confirmed_tick(peer.player_id).is_none()
+        });
+        if unannounced {
+            return 0;
+        }
+        match self.input_tracker.min_confirmed_tick() {
+            Some(confirmed_tick) => confirmed_tick.min(self.last_tick),
+            None => self.last_tick,
+        }
+    }
+
+    //Players only. Promises the others that we will not issue any more requests for ticks we have simulated.
+    fn announce_input_progress(&mut self) {
+        if self.is_spectator || self.is_server || self.last_tick <= self.last_announced_tick {
+            return;
+        }
+        self.last_announced_tick = self.last_tick;
+        let progress = InputProgress { player_id: self.local_player_id, tick: self.last_tick, sequence: self.request_sequence };
+        self.send_message(NetworkMessage::InputProgress(progress));
+    }
+
+    /*Sends the checksum of every CHECKSUM_INTERVAL-th tick confirmed since the last exchange and compares it with the
+    remote one. Every player has sent its requests for a confirmed tick, so no request can still roll it back and its
+    checksum is final. Confirmed ticks already dropped from the history, after a long stall, are skipped.*/
+    fn exchange_checksums(&mut self) {
+        if self.is_server || self.authority.is_some() {
+            return; //Nothing to compare, the players are corrected to the server's state instead.
+        }
+        let confirmed_tick = self.confirmed_tick();
+        let mut checksum_tick = (self.last_checksum_tick / CHECKSUM_INTERVAL + 1) * CHECKSUM_INTERVAL;
+        while checksum_tick <= confirmed_tick {
+            if let Some(simulation_state) = self.game_state_history.get(&checksum_tick) {
+                let checksum = simulation_state.calculate_checksum(checksum_tick);
+                let echoes_sent_messages = self.transport.as_ref().is_some_and(|transport| transport.echoes_sent_messages());
+                if !echoes_sent_messages {
+                    self.send_message(NetworkMessage::Checksum(checksum.clone()));
+                }
+                if let Some(report) = self.desync_detector.add_local(checksum) {
+                    println!("{}", report);
+                }
+            }
+            checksum_tick += CHECKSUM_INTERVAL;
+        }
+        self.last_checksum_tick = self.last_checksum_tick.max(confirmed_tick);
+    }
+
+    /*Server only. Every AUTHORITATIVE_STATE_INTERVAL ticks, sends the players its state for the newest tick they have
+    all sent their requests for, which can no longer be rolled back.*/
+    fn broadcast_authoritative_state(&mut self, tick: Tick) {
+        if !self.is_server || !tick.is_multiple_of(AUTHORITATIVE_STATE_INTERVAL) {
+            return;
+        }
+        let confirmed_tick = self.confirmed_tick();
+        if let Some(simulation_state) = self.game_state_history.get(&confirmed_tick) {
+            let snapshot = GameStateSnapshot::new(confirmed_tick, simulation_state.clone(), &self.requests);
+            self.send_message(NetworkMessage::AuthoritativeState(snapshot));
+        }
+    }
+
+    /*Server-authoritative matches. If the server's state for a tick differs from ours, ours is replaced with it and
+    the ticks since are simulated again on top, so a player whose state diverged or was edited is pulled back.*/
+    pub fn reconcile(&mut self, snapshot: GameStateSnapshot) {
+        if snapshot.tick > self.last_tick || self.awaiting_resync {
+            //Behind the server, there is nothing of ours to correct.
+            self.awaiting_resync = true;
+            self.load_snapshot(snapshot);
+            return;
+        }
+        let tick = snapshot.tick;
+        let local_checksum = match self.game_state_history.get(&tick) {
+            Some(simulation_state) => simulation_state.calculate_checksum(tick).checksum,
+            None => return, //Older than our history, e.g. delayed behind a newer one, there is nothing left to compare.
+        };
+        if local_checksum == snapshot.state.calculate_checksum(tick).checksum {
+            return;
+        }
+        println!("Corrected to the server's state at tick {}", tick);
+        self.corrections += 1;
+        self.add_snapshot_requests(&snapshot);
+        self.game_state_history.insert(tick, snapshot.state);
+        self.rollback(tick + 1);
+    }
+
+    fn send_message(&mut self, message: NetworkMessage) {
+        if self.transport.is_some() {
+            self.outgoing_messages.push(message);
+        }
+    }
+
+    //Sends everything queued since the last flush as one binary frame.
+    pub fn flush_outgoing_messages(&mut self) {
+        self.announce_input_progress();
+        if self.outgoing_messages.is_empty() {
+            return;
+        }
+        let messages = std::mem::take(&mut self.outgoing_messages);
+        if let Some(transport) = &mut self.transport {
+            for batch in messages.chunks(u16::MAX as usize) {
+                transport.send_bytes(&wireformat::encode_frame(batch));
+            }
+        }
+    }
+
+    //Asks the other peers for a snapshot. Only one resync is in flight at a time.
+    fn request_resync(&mut self) {
+        if self.awaiting_resync {
+            return;
+        }
+        println!("Requesting resync at tick {}", self.last_tick);
+        self.awaiting_resync = true;
+        self.send_message(NetworkMessage::ResyncRequest);
+    }
+
+    //Call once a frame with the wall clock. Pings the other peers when due so the input delay can follow their latency.
+    pub fn update_latency(&mut self, time: f64) {
+        if self.is_spectator {
+            return;
+        }
+        if let Some(ping) = self.latency.update(time, self.local_player_id) {
+            self.send_message(NetworkMessage::Ping(ping));
         }
     }
 
-    pub fn process_request(&mut self, request: &Request) {
+    //Ticks between issuing a request and executing it. Offline there is nobody to wait for.
+    pub fn input_delay(&self) -> Tick {
+        if self.transport.as_ref().is_some_and(|transport| transport.echoes_sent_messages()) {
+            return MIN_INPUT_DELAY;
+        }
+        self.latency.input_delay_ticks()
+    }
+
+    //Spectators can join mid-match, so they start from a snapshot of the match rather than from tick 0.
+    pub fn join_as_spectator(&mut self) {
+        self.is_spectator = true;
+        self.request_resync();
+    }
+
+    fn send_snapshot(&mut self) {
+        let snapshot = GameStateSnapshot::capture(&self.current_game_state, self.last_tick, &self.requests);
+        self.send_message(NetworkMessage::Snapshot(snapshot));
+    }
+
+    /*Replaces the local simulation with the snapshot and resumes lockstep from its tick. Requests we have queued
+    that the sender did not know about yet are kept so they still execute.*/
+    pub fn load_snapshot(&mut self, snapshot: GameStateSnapshot) {
+        if !self.awaiting_resync {
+            return;
+        }
+        println!("Resynchronized to snapshot at tick {}", snapshot.tick);
+        self.current_game_state.restore_simulation_state(&snapshot.state);
+        self.add_snapshot_requests(&snapshot);
+        for received in &snapshot.received_sequences {
+            self.requests.MarkReceivedUpTo(*received);
+        }
+        self.input_tracker.reset_to(snapshot.tick);
+        for (player_id, _) in self.input_tracker.get_all_confirmed_ticks() {
+            self.input_tracker.update(player_id, self.requests.GetReceivedSequence(player_id));
+        }
+        self.last_tick = snapshot.tick;
+        self.game_state_history.clear();
+        self.game_state_history.insert(snapshot.tick, snapshot.state);
+        self.desync_detector = DesyncDetector::default();
+        self.awaiting_resync = false;
+    }
+
+    fn add_snapshot_requests(&mut self, snapshot: &GameStateSnapshot) {
+        /*The sender's requests before its oldest queued one were executed before the snapshot was taken. Skipping
+        them keeps the queued ones within MAX_SEQUENCE_GAP even when we are thousands of requests behind.*/
+        for received in &snapshot.received_sequences {
+            let oldest_queued = snapshot.requests.iter().filter(|request| request.get_player_id() == received.player_id).map(|request| request.get_id().sequence).min();
+            let executed = oldest_queued.map_or(received.sequence, |oldest_queued| oldest_queued.saturating_sub(1).min(received.sequence));
+            self.requests.MarkReceivedUpTo(RequestID { player_id: received.player_id, sequence: executed });
+        }
+        for request in &snapshot.requests {
+            if self.requests.AddRequest(*request) {
+                if let Some(replay_recorder) = &mut self.replay_recorder {
+                    replay_recorder.record(request);
+                }
+            }
+        }
+    }
+
+    pub fn validate_request(&self, request: &Request) -> Result<(), RequestError> {
         match request {
             Request::SpriteMove(sprite_move) => {
-                match &mut self
+                match self.current_game_state.get_unit_owner(sprite_move.sprite_uuid) {
+                    None => return Err(RequestError::UnknownUnit(sprite_move.sprite_uuid)),
+                    Some(owner) if owner != sprite_move.player_id => {
+                        return Err(RequestError::NotOwner { sprite_uuid: sprite_move.sprite_uuid, owner, player_id: sprite_move.player_id })
+                    }
+                    Some(_owner) => {}
+                }
+                if !self.pathfinder.is_within_map(sprite_move.position.x, sprite_move.position.y) {
+                    return Err(RequestError::OutOfBounds(sprite_move.position));
+                }
+            }
+            Request::SpriteCreate(sprite_create) => {
+                if self.current_game_state.sprite_map.contains_key(&sprite_create.sprite_uuid) {
+                    return Err(RequestError::DuplicateUnit(sprite_create.sprite_uuid));
+                }
+                /*The uuid has to come from the creator's own block, or it could take an id another player is yet to
+                allocate. Without player_slots, e.g. in tests, any player block will do, but never the tiles' block.*/
+                let expected_slot = match self.player_slots.get(&sprite_create.player_id) {
+                    Some(slot) => Some(*slot),
+                    None if self.player_slots.is_empty() => IdAllocator::player_slot_of(sprite_create.sprite_uuid),
+                    None => None,
+                };
+                if expected_slot.is_none() || IdAllocator::player_slot_of(sprite_create.sprite_uuid) != expected_slot {
+                    return Err(RequestError::ForeignId { sprite_uuid: sprite_create.sprite_uuid, player_id: sprite_create.player_id });
+                }
+                if !self.pathfinder.is_within_map(sprite_create.position.x, sprite_create.position.y) {
+                    return Err(RequestError::OutOfBounds(sprite_create.position));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn process_request(&mut self, request: &Request) -> Result<(), RequestError> {
+        self.validate_request(request)?;
+        match request {
+            Request::SpriteMove(sprite_move) => {
+                match self
                     .current_game_state
                     .sprite_map
                     .get_mut(&sprite_move.sprite_uuid)
-                    .unwrap()
                 {
-                    SpriteID::Engineer(engineer_entity) => {
+                    Some(SpriteID::Engineer(engineer_entity)) => {
                         let mut path = self.pathfinder.find_path(
                             TilePosition {
                                 x: engineer_entity.get_tile_pos().x as i32,
                                 y: engineer_entity.get_tile_pos().y as i32,
                             },
                             TilePosition {
-                                x: (sprite_move.position.x ) as i32,
-                                y: (sprite_move.position.y) as i32,
+                                x: (sprite_move.position.x ),
+                                y: (sprite_move.position.y),
                             },
                         );
                         engineer_entity.update_path(std::mem::take(&mut path))
                     }
-                    SpriteID::Mech(mech_entity) => {
+                    Some(SpriteID::Mech(mech_entity)) => {
                         let mut path = self.pathfinder.find_path(
                             TilePosition {
                                 x: mech_entity.get_tile_pos().x as i32,
                                 y: mech_entity.get_tile_pos().y as i32,
                             },
                             TilePosition {
-                                x: (sprite_move.position.x ) as i32,
-                                y: (sprite_move.position.y) as i32,
+                                x: (sprite_move.position.x ),
+                                y: (sprite_move.position.y),
                             },
                         );
                         mech_entity.update_path(std::mem::take(&mut path))
@@ -101,90 +401,299 @@ impl GameManager {
                 match request.sprite_type {
                     crate::model::requests::SpriteType::Engineer =>
                     {
-                        let engy_sprite =  Engineer::new(request.position.x, request.position.y, 64, 64, Texture2D::from_file_with_format(include_dir!("assets").get_file("spritesheet_rock.png").unwrap().contents(),None), request.sprite_uuid, Texture2D::from_file_with_format(include_dir!("assets").get_file("selected.png").unwrap().contents(), None));
+                        let engy_sprite =  Engineer::new(request.position.x, request.position.y, 64, 64, request.sprite_uuid, request.player_id);
                         self.current_game_state.sprite_map.insert(request.sprite_uuid, SpriteID::Engineer(engy_sprite));
                         self.current_game_state.sprite_uuid_list.push(request.sprite_uuid);
                         
                     },
                     crate::model::requests::SpriteType::Mech =>
                     {
-                        let engy_sprite =  Mech::new(request.position.x, request.position.y, 64, 64, request.sprite_uuid, Texture2D::from_file_with_format(include_dir!("assets").get_file("selected.png").unwrap().contents(), None));
+                        let engy_sprite =  Mech::new(request.position.x, request.position.y, 64, 64, request.sprite_uuid, request.player_id);
                         self.current_game_state.sprite_map.insert(request.sprite_uuid, SpriteID::Mech(engy_sprite));
                         self.current_game_state.sprite_uuid_list.push(request.sprite_uuid);
                         
                     }
-                    _default => {}
             }
         }
     }
+    Ok(())
 }
 
-    pub fn render(&mut self) {
-        self.current_game_state.render();
+    pub fn allocate_unit_id(&mut self) -> u32 {
+        self.id_allocator.next_id()
+    }
+
+    pub fn next_request_sequence(&mut self) -> u32 {
+        self.request_sequence += 1;
+        self.request_sequence
     }
-        
+
+    #[allow(non_snake_case)]
     pub fn addLocalRequest(&mut self, request: Request) -> RequestStatus {
+        if self.is_spectator {
+            println!("Spectators cannot issue requests");
+            return RequestStatus::Synchronized;
+        }
         //Send Request Over Network Here
         /*
         */
-        self.socket.send_text(serde_json::to_string(&request).unwrap().as_str());
-        return self.addRequest(request);
+        let echoes_sent_messages = self.transport.as_ref().is_some_and(|transport| transport.echoes_sent_messages());
+        self.send_message(NetworkMessage::Request(request));
+        if echoes_sent_messages {
+            //Request will be queued when it is received back in getNetworkRequests.
+            return RequestStatus::Synchronized;
+        }
+        self.addRequest(request)
        
     }
+    #[allow(non_snake_case)]
     pub fn getNetworkRequests(&mut self) {
-        
-        while let Some(event) =  self.socket.try_recv(){
-            println!("Received {:?}", event);
-            match serde_json::from_str(std::str::from_utf8(&event).unwrap())
-            {
-                Err(e) => {
-                    println!("Error:{}", e);
+        self.flush_outgoing_messages();
+        let mut events = Vec::new();
+        if let Some(transport) = &mut self.transport {
+            while let Some(event) = transport.poll_event() {
+                events.push(event);
+            }
+        }
+        for event in events {
+            match event {
+                TransportEvent::Message(message) => self.receive_message(&message),
+                TransportEvent::Connected => {
+                    println!("Transport connected");
+                    if self.connection_lost {
+                        //Anything sent while we were away is lost, including answers to retransmission requests.
+                        self.connection_lost = false;
+                        self.requested_retransmissions.clear();
+                        self.send_received_requests(false);
+                    }
                 }
-                Ok(response) =>
-                {
-                    self.addNetworkRequest(response);
+                TransportEvent::Disconnected => {
+                    println!("Transport disconnected");
+                    self.connection_lost = true;
                 }
+                TransportEvent::Error(e) => println!("Transport error: {}", e),
             }
+        }
+        self.request_missing_requests();
+    }
 
+    //Asks each player once for the requests of theirs that later requests skipped over.
+    fn request_missing_requests(&mut self) {
+        let missing = self.requests.GetMissingRequests();
+        self.requested_retransmissions.retain(|id| missing.contains(id));
+        let mut sequences_by_player: std::collections::BTreeMap<PlayerID, Vec<u32>> = std::collections::BTreeMap::new();
+        for id in missing {
+            if id.player_id != self.local_player_id && self.requested_retransmissions.insert(id) {
+                sequences_by_player.entry(id.player_id).or_default().push(id.sequence);
+            }
+        }
+        for (player_id, sequences) in sequences_by_player {
+            println!("Asking player {} to retransmit requests {:?}", player_id, sequences);
+            self.send_message(NetworkMessage::RetransmitRequest(RetransmitRequest { player_id, sequences }));
+        }
+    }
+
+    fn send_received_requests(&mut self, is_reply: bool) {
+        let received = ReceivedRequests { sender: self.local_player_id, received: self.requests.GetReceivedSequences(), is_reply };
+        self.send_message(NetworkMessage::ReceivedRequests(received));
+    }
+
+    //Resends our requests that `sender` has not received. Ones already purged are too old to roll back to anyway.
+    fn answer_received_requests(&mut self, received_requests: ReceivedRequests) {
+        if received_requests.sender == self.local_player_id {
+            return;
+        }
+        let received_sequence = received_requests
+            .received
+            .iter()
+            .find(|id| id.player_id == self.local_player_id)
+            .map_or(0, |id| id.sequence);
+        let missed_requests = self.requests.GetRequestsOfPlayerAfter(self.local_player_id, received_sequence);
+        if !missed_requests.is_empty() {
+            println!("Resending {} requests to player {}", missed_requests.len(), received_requests.sender);
+        }
+        for request in missed_requests {
+            self.send_message(NetworkMessage::Request(request));
+        }
+        if !received_requests.is_reply {
+            self.send_received_requests(true);
+        }
+    }
+
+    //Requests already purged are not sent, the asking peer will find them too old anyway and resync.
+    fn retransmit_requests(&mut self, retransmit_request: RetransmitRequest) {
+        if retransmit_request.player_id != self.local_player_id || self.is_spectator {
+            return;
+        }
+        for sequence in retransmit_request.sequences {
+            if let Some(request) = self.requests.GetRequest(RequestID { player_id: self.local_player_id, sequence }) {
+                self.send_message(NetworkMessage::Request(request));
+            }
         }
-       
     }
+    //Game traffic arrives as binary frames, anything else is a late JSON handshake message.
+    pub fn receive_message(&mut self, message: &[u8]) {
+        if !wireformat::is_frame(message) {
+            match serde_json::from_slice(message) {
+                Ok(HandshakeMessage::Hello(remote_hello)) => self.answer_hello(remote_hello),
+                Ok(HandshakeMessage::HelloRejected { player_id, rejection }) if player_id == self.local_player_id => println!("Rejected by peer: {}", rejection),
+                Ok(HandshakeMessage::HelloRejected { .. }) => {}
+                Ok(HandshakeMessage::Lobby(_)) => {}
+                Err(e) => println!("Error:{}", e),
+            }
+            return;
+        }
+        let network_messages = match wireformat::decode_frame(message) {
+            Ok(network_messages) => network_messages,
+            Err(e) => {
+                println!("Error:{}", e);
+                return;
+            }
+        };
+        for network_message in network_messages {
+            self.receive_network_message(network_message);
+        }
+    }
+    fn receive_network_message(&mut self, message: NetworkMessage) {
+        match message
+        {
+            NetworkMessage::Request(response) =>
+            {
+                self.addNetworkRequest(response);
+            }
+            NetworkMessage::Checksum(checksum) =>
+            {
+                if let Some(report) = self.desync_detector.add_remote(checksum) {
+                    println!("{}", report);
+                }
+            }
+            /*Only players answer, a spectator's copy of the match may itself still be waiting on a snapshot. With a server,
+            only the server answers.*/
+            NetworkMessage::ResyncRequest => {
+                if !self.is_spectator && self.authority.is_none() {
+                    self.send_snapshot();
+                }
+            }
+            NetworkMessage::Snapshot(snapshot) => self.load_snapshot(snapshot),
+            //Spectators do not answer, their latency should not hold back the players' input.
+            NetworkMessage::Ping(ping) => {
+                if ping.sender != self.local_player_id && !self.is_spectator {
+                    self.send_message(NetworkMessage::Pong(Pong { ping, responder: self.local_player_id }));
+                }
+            }
+            NetworkMessage::Pong(pong) => {
+                if pong.ping.sender == self.local_player_id {
+                    self.latency.add_pong(&pong);
+                }
+            }
+            NetworkMessage::RetransmitRequest(retransmit_request) => self.retransmit_requests(retransmit_request),
+            NetworkMessage::ReceivedRequests(received_requests) => self.answer_received_requests(received_requests),
+            NetworkMessage::InputProgress(progress) => {
+                if progress.player_id != self.local_player_id {
+                    let received_sequence = self.requests.GetReceivedSequence(progress.player_id);
+                    if !self.input_tracker.add_progress(progress, received_sequence) && !self.is_server {
+                        println!("Player {} announced more progress than we can track without its requests", progress.player_id);
+                        self.request_resync();
+                    }
+                }
+            }
+            NetworkMessage::AuthoritativeState(snapshot) => {
+                if !self.is_server {
+                    self.reconcile(snapshot);
+                }
+            }
+        }
+    }
+    /*A hello after the handshake is either a known peer's final repeat, which is not answered (that would echo
+    forever), or a peer joining mid-match such as a spectator, which gets our hello back so its handshake completes.*/
+    fn answer_hello(&mut self, remote_hello: Hello) {
+        let local_hello = match &self.local_hello {
+            Some(local_hello) => local_hello.clone(),
+            None => return,
+        };
+        match local_hello.check_compatible(&remote_hello) {
+            Ok(()) => {
+                if self.peers.iter().any(|peer| peer.player_id == remote_hello.player_id) {
+                    return;
+                }
+                println!("{} (player {}) joined as {:?}", remote_hello.player_name, remote_hello.player_id, remote_hello.role);
+                self.peers.push(remote_hello);
+                self.player_slots = local_hello.player_slots(&self.peers);
+                if let Some(transport) = &mut self.transport {
+                    transport.send_text(serde_json::to_string(&HandshakeMessage::Hello(local_hello)).unwrap().as_str());
+                }
+            }
+            Err(rejection) => {
+                println!("Rejecting peer {}: {}", remote_hello.player_id, rejection);
+                if let Some(transport) = &mut self.transport {
+                    let message = HandshakeMessage::HelloRejected { player_id: remote_hello.player_id, rejection };
+                    transport.send_text(serde_json::to_string(&message).unwrap().as_str());
+                }
+            }
+        }
+    }
+    #[allow(non_snake_case)]
     pub fn addNetworkRequest(&mut self, request: Request) -> RequestStatus {
-        return self.addRequest(request);
+        self.addRequest(request)
        
     }
+    #[allow(non_snake_case)]
     pub fn addRequest(&mut self, request: Request) -> RequestStatus {
-        self.requests.AddRequest(request);
-        if (request.get_tick() < self.last_tick && (self.last_tick - request.get_tick()) < 45)
+        if self.requests.IsTooFarAhead(request.get_id()) {
+            //Too many of the sender's requests are missing to ask for them, a snapshot brings us back in line.
+            println!("Ignoring request {} of player {}, too far ahead of the {} we have", request.get_id().sequence, request.get_player_id(), self.requests.GetReceivedSequence(request.get_player_id()));
+            if !self.is_server {
+                self.request_resync();
+            }
+            return RequestStatus::Desynchronized;
+        }
+        //A request received twice, e.g. retransmitted after a reconnect, is only queued and executed once.
+        if !self.requests.AddRequest(request) {
+            return RequestStatus::Synchronized;
+        }
+        if let Some(replay_recorder) = &mut self.replay_recorder {
+            replay_recorder.record(&request);
+        }
+        let player_id = request.get_player_id();
+        self.input_tracker.update(player_id, self.requests.GetReceivedSequence(player_id));
+        let can_roll_back = request.get_tick() == 0 || self.game_state_history.contains_key(&(request.get_tick() - 1));
+        if request.get_tick() <= self.last_tick && can_roll_back
         //Received old request. Time to synchronize
         {
             self.process_tick(request.get_tick());
-            return RequestStatus::Synchronized;
-        } else if self.last_tick > 45 && request.get_tick() < self.last_tick &&  (self.last_tick - request.get_tick()) > 45
+            RequestStatus::Synchronized
+        } else if request.get_tick() <= self.last_tick && self.is_server
+        //The server's simulation is the reference, there is no one to resync from.
+        {
+            println!("Dropping request from player {} for tick {}, too late to roll back to", player_id, request.get_tick());
+            RequestStatus::Desynchronized
+        } else if request.get_tick() <= self.last_tick
         //Request is too old. Game State is desynchronized.
         {
-            return RequestStatus::Desynchronized;
+            self.request_resync();
+            RequestStatus::Desynchronized
         } else {
-            if (self.last_tick > 47) {
-                self.requests
-                    .PurgeRequestsOlderThanTick(self.last_tick - 47);
-            }
-            return RequestStatus::Synchronized;
+            RequestStatus::Synchronized
         }
     }
     pub fn mouse_clicked(&mut self, mouse_coords: Vec2) {
+        if self.is_spectator {
+            return;
+        }
         let selected_unit_uuid = self
             .current_game_state
             .is_sprite_within_bounds(mouse_coords); //if it is within bounds, selection has occured. If it is not within bounds, move or other operation has been requested.
         match selected_unit_uuid {
             Some(unit_uuid) => {
-                self.current_game_state.mark_new_selected_sprite(unit_uuid);
+                self.current_game_state.mark_new_selected_sprite(unit_uuid, self.local_player_id);
             }
             None => {
                 /*Move Request*/
-                if (self.current_game_state.selected_entity != 0) {
+                if self.current_game_state.selected_entity != 0 {
                     let request = Request::SpriteMove(SpriteMoveRequest {
-                        tick: self.last_tick + 10,
+                        tick: self.last_tick + self.input_delay(),
+                        player_id: self.local_player_id,
+                        sequence: self.next_request_sequence(),
                         sprite_uuid: self.current_game_state.selected_entity,
                         position: TilePosition {
                             x: (world_to_grid_coords(mouse_coords).x - 1.0) as i32,
@@ -197,3 +706,753 @@ impl GameManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::requests::{SpriteCreateRequest, SpriteType};
+    use crate::model::inputtracker::MAX_PENDING_PROGRESS;
+    use crate::model::random::SimulationRng;
+    use crate::sprites::sprite::grid_to_world_coords;
+    use crate::network::latency::DEFAULT_INPUT_DELAY;
+    use crate::network::loopbacktransport::{LoopbackRoom, LoopbackTransport};
+    use crate::network::reconnectingtransport::ReconnectingTransport;
+    use crate::network::simulatedtransport::{NetworkConditions, SimulatedTransport};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use crate::tiledmap;
+    use include_dir::include_dir;
+    use include_dir::Dir;
+
+    //Unit uuids as IdAllocator hands them out to the players in slots 0, 1 and 2.
+    const UNIT_1: u32 = 1 << 24;
+    const UNIT_2: u32 = 2 << 24;
+    const UNIT_3: u32 = 3 << 24;
+
+    fn create_headless_game_manager() -> GameManager {
+        static ASSETS_DIR: Dir = include_dir!("assets");
+        let body = ASSETS_DIR.get_file("tiledmap.json").unwrap().contents_utf8().unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
+        let sprite_map = tiledmap::get_tilemap_spritelist(&map_cast);
+        let sprite_uuid_list: Vec<u32> = sprite_map.keys().cloned().collect();
+        let game_state = GameState{sprite_map, sprite_uuid_list, selected_entity: 0, rng: SimulationRng::new(42)};
+        GameManager::new(game_state, Pathfinder::new(map_cast), None)
+    }
+
+    /*Game managers for players 1 to `count` in slots 0 to count - 1, connected to each other as through the relay
+    server and each knowing the others as peers, as after the handshake.*/
+    fn connected_game_managers(count: usize) -> Vec<GameManager> {
+        let hellos: Vec<Hello> = (1..=count as u32).map(|player_id| Hello::new("map".to_string(), player_id, format!("Player {}", player_id), player_id as u64)).collect();
+        let room = LoopbackRoom::default();
+        let mut game_managers = Vec::new();
+        for i in 0..count {
+            let mut game_manager = create_headless_game_manager();
+            game_manager.transport = Some(Box::new(room.join()));
+            game_manager.local_player_id = hellos[i].player_id;
+            game_manager.id_allocator = IdAllocator::for_player_slot(i as u8);
+            game_manager.peers = hellos.iter().filter(|hello| hello.player_id != hellos[i].player_id).cloned().collect();
+            game_manager.player_slots = hellos[i].player_slots(&game_manager.peers);
+            game_managers.push(game_manager);
+        }
+        game_managers
+    }
+
+    #[test]
+    fn headless_process_tick_moves_engineer() {
+        let mut game_manager = create_headless_game_manager();
+        game_manager.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 0, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        game_manager.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 0, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}));
+        for tick in 1..200 {
+            game_manager.process_tick(tick);
+        }
+        match game_manager.current_game_state.sprite_map.get(&UNIT_1).unwrap() {
+            SpriteID::Engineer(engineer_entity) => {
+                assert_eq!(engineer_entity.x, 5);
+                assert_eq!(engineer_entity.y, 5);
+                assert_eq!(engineer_entity.current_path.len(), 0);
+            }
+            _default => panic!("Sprite 1 should be an Engineer"),
+        }
+    }
+
+    #[test]
+    fn late_requests_roll_back_to_the_straight_through_result() {
+        let requests = vec![
+            Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 0, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}),
+            Request::SpriteCreate(SpriteCreateRequest {tick: 3, player_id: 1, sequence: 1, sprite_uuid: UNIT_2, position: TilePosition{x: 8, y: 2}, sprite_type: SpriteType::Mech}),
+            Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 0, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}),
+            Request::SpriteMove(SpriteMoveRequest {tick: 12, player_id: 1, sequence: 2, sprite_uuid: UNIT_2, position: TilePosition{x: 2, y: 8}}),
+            Request::SpriteMove(SpriteMoveRequest {tick: 12, player_id: 0, sequence: 3, sprite_uuid: UNIT_1, position: TilePosition{x: 7, y: 6}}),
+            Request::SpriteMove(SpriteMoveRequest {tick: 30, player_id: 0, sequence: 4, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 8}}),
+        ];
+        //How many ticks after its own tick each request arrives, 0 meaning just after that tick was simulated.
+        let delays: [Tick; 6] = [20, 1, 0, 15, 3, 40];
+
+        let mut expected = Vec::new();
+        let mut straight_through = create_headless_game_manager();
+        for request in &requests {
+            straight_through.addRequest(*request);
+        }
+        for tick in 1..200 {
+            straight_through.process_tick(tick);
+            expected.push(straight_through.current_game_state.capture_simulation_state().calculate_checksum(tick));
+        }
+
+        let mut game_manager = create_headless_game_manager();
+        for tick in 1..200 {
+            game_manager.process_tick(tick);
+            for (request, delay) in requests.iter().zip(delays.iter()) {
+                if request.get_tick() + delay == tick {
+                    game_manager.addRequest(*request);
+                }
+            }
+        }
+        //Every tick still in the history matches, not just the latest one.
+        for tick in (200 - game_manager.rollback_window)..200 {
+            assert_eq!(game_manager.game_state_history.get(&tick).unwrap().calculate_checksum(tick), expected[tick as usize - 1], "at tick {}", tick);
+        }
+        assert_eq!(game_manager.current_game_state.capture_simulation_state().calculate_checksum(199), expected[198]);
+        assert!(!game_manager.awaiting_resync);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected_without_panicking() {
+        let mut game_manager = create_headless_game_manager();
+        let create = Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 0, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer});
+        //Move for a unit that is only created later.
+        game_manager.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 1, player_id: 0, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}));
+        game_manager.addLocalRequest(create);
+        for tick in 1..5 {
+            game_manager.process_tick(tick);
+        }
+        assert_eq!(game_manager.current_game_state.sprite_map.get(&UNIT_1).unwrap().get_tile_pos(), Vec2::new(1.0, 1.0));
+
+        assert_eq!(game_manager.process_request(&create), Err(RequestError::DuplicateUnit(UNIT_1)));
+        assert_eq!(
+            game_manager.process_request(&Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 0, sequence: 3, sprite_uuid: 99, position: TilePosition{x: 5, y: 5}})),
+            Err(RequestError::UnknownUnit(99))
+        );
+        assert_eq!(
+            game_manager.process_request(&Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 0, sequence: 4, sprite_uuid: UNIT_1, position: TilePosition{x: 5000, y: -1}})),
+            Err(RequestError::OutOfBounds(TilePosition{x: 5000, y: -1}))
+        );
+        assert_eq!(
+            game_manager.process_request(&Request::SpriteCreate(SpriteCreateRequest {tick: 5, player_id: 0, sequence: 5, sprite_uuid: UNIT_2, position: TilePosition{x: -3, y: 1}, sprite_type: SpriteType::Mech})),
+            Err(RequestError::OutOfBounds(TilePosition{x: -3, y: 1}))
+        );
+        assert!(!game_manager.current_game_state.sprite_map.contains_key(&UNIT_2));
+    }
+
+    #[test]
+    fn created_unit_ids_must_come_from_the_creators_block() {
+        let mut game_manager = create_headless_game_manager();
+        let create = |player_id: PlayerID, sprite_uuid: u32| {
+            Request::SpriteCreate(SpriteCreateRequest {tick: 1, player_id, sequence: 1, sprite_uuid, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer})
+        };
+        let tile_uuid = game_manager.current_game_state.sprite_uuid_list[0];
+        assert_eq!(game_manager.validate_request(&create(1, tile_uuid)), Err(RequestError::DuplicateUnit(tile_uuid)));
+        //Unused, but in the tiles' block.
+        assert_eq!(game_manager.validate_request(&create(1, UNIT_1 - 1)), Err(RequestError::ForeignId { sprite_uuid: UNIT_1 - 1, player_id: 1 }));
+        //Without player slots any player block is accepted.
+        assert_eq!(game_manager.validate_request(&create(1, UNIT_2)), Ok(()));
+
+        game_manager.player_slots = vec![(1, 0), (2, 1)].into_iter().collect();
+        assert_eq!(game_manager.validate_request(&create(1, UNIT_1)), Ok(()));
+        assert_eq!(game_manager.validate_request(&create(2, UNIT_2)), Ok(()));
+        assert_eq!(game_manager.validate_request(&create(1, UNIT_2)), Err(RequestError::ForeignId { sprite_uuid: UNIT_2, player_id: 1 }));
+        assert_eq!(game_manager.validate_request(&create(2, UNIT_3)), Err(RequestError::ForeignId { sprite_uuid: UNIT_3, player_id: 2 }));
+        //A player outside the match has no block at all.
+        assert_eq!(game_manager.validate_request(&create(3, UNIT_3)), Err(RequestError::ForeignId { sprite_uuid: UNIT_3, player_id: 3 }));
+    }
+
+    #[test]
+    fn loopback_requests_are_applied_once() {
+        let mut game_manager = create_headless_game_manager();
+        game_manager.transport = Some(Box::new(LoopbackTransport::new()));
+        let tile_count = game_manager.current_game_state.sprite_uuid_list.len();
+        game_manager.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 0, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Mech}));
+        assert_eq!(game_manager.requests.GetNumberOfRequests(), 0);
+        game_manager.getNetworkRequests();
+        assert_eq!(game_manager.requests.GetNumberOfRequests(), 1);
+        for tick in 1..5 {
+            game_manager.process_tick(tick);
+        }
+        assert_eq!(game_manager.current_game_state.sprite_uuid_list.len(), tile_count + 1);
+    }
+
+    #[test]
+    fn duplicate_requests_are_dropped_and_missing_ones_retransmitted() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+
+        let create = Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer});
+        let lost_move = Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}});
+        let last_move = Request::SpriteMove(SpriteMoveRequest {tick: 40, player_id: 1, sequence: 3, sprite_uuid: UNIT_1, position: TilePosition{x: 2, y: 6}});
+        game_manager_a.addLocalRequest(create);
+        game_manager_a.addRequest(lost_move); //Queued by A, but never reaches B.
+        game_manager_a.addLocalRequest(last_move);
+        game_manager_a.send_message(NetworkMessage::Request(create)); //Delivered twice.
+        for tick in 1..100 {
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            if tick == 1 {
+                assert_eq!(game_manager_b.requests.GetNumberOfRequests(), 2);
+                assert_eq!(game_manager_b.requested_retransmissions.len(), 1);
+            }
+        }
+
+        //Executed and purged since, but all three were received.
+        assert_eq!(game_manager_b.requests.GetReceivedSequences(), vec![RequestID { player_id: 1, sequence: 3 }]);
+        assert!(game_manager_b.requests.GetMissingRequests().is_empty());
+        assert!(game_manager_b.requested_retransmissions.is_empty());
+        assert_eq!(
+            game_manager_a.current_game_state.capture_simulation_state().calculate_checksum(99),
+            game_manager_b.current_game_state.capture_simulation_state().calculate_checksum(99)
+        );
+    }
+
+    #[test]
+    fn peer_too_far_ahead_is_caught_up_with_by_a_snapshot() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+        //A has issued thousands of requests B never saw, far more than B would ask to be retransmitted.
+        game_manager_a.requests.MarkReceivedUpTo(RequestID { player_id: 1, sequence: 5000 });
+        game_manager_a.request_sequence = 5000;
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 20, player_id: 1, sequence: 5001, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        for tick in 1..60 {
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            if tick == 1 {
+                assert!(game_manager_b.awaiting_resync);
+                assert!(game_manager_b.requested_retransmissions.is_empty());
+            }
+        }
+
+        assert!(!game_manager_b.awaiting_resync);
+        assert_eq!(game_manager_b.requests.GetReceivedSequence(1), 5001);
+        assert!(game_manager_b.current_game_state.sprite_map.contains_key(&UNIT_1));
+        assert_eq!(
+            game_manager_a.current_game_state.capture_simulation_state().calculate_checksum(59),
+            game_manager_b.current_game_state.capture_simulation_state().calculate_checksum(59)
+        );
+    }
+
+    #[test]
+    fn requests_missed_while_disconnected_are_resent_after_reconnecting() {
+        //B reaches A through a connection that is re-established on every reconnect, as through the relay server.
+        let time = Rc::new(Cell::new(0.0));
+        let far_end: Rc<RefCell<Option<LoopbackTransport>>> = Rc::new(RefCell::new(None));
+        let connector_far_end = far_end.clone();
+        let clock_time = time.clone();
+        let transport_b = ReconnectingTransport::new(
+            Box::new(move || {
+                let room = LoopbackRoom::default();
+                let (local, remote) = (room.join(), room.join());
+                *connector_far_end.borrow_mut() = Some(remote);
+                Ok(Box::new(local) as Box<dyn Transport>)
+            }),
+            Box::new(move || clock_time.get()),
+        );
+        let mut game_manager_a = create_headless_game_manager();
+        let mut game_manager_b = create_headless_game_manager();
+        game_manager_a.transport = Some(Box::new(far_end.borrow_mut().take().unwrap()));
+        game_manager_b.transport = Some(Box::new(transport_b));
+        game_manager_a.local_player_id = 1;
+        game_manager_b.local_player_id = 2;
+
+        let sequence = game_manager_a.next_request_sequence();
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        for tick in 1..150 {
+            time.set(tick as f64 * TICK_DURATION);
+            if tick == 20 {
+                game_manager_a.transport.as_mut().unwrap().disconnect();
+            }
+            //Issued while B is away, so only a resend gets it there.
+            if tick == 22 || tick == 24 {
+                let sequence = game_manager_a.next_request_sequence();
+                game_manager_a.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: tick + 3, player_id: 1, sequence, sprite_uuid: UNIT_1, position: TilePosition{x: tick as i32 / 4, y: 5}}));
+            }
+            if let Some(transport) = far_end.borrow_mut().take() {
+                game_manager_a.transport = Some(Box::new(transport));
+            }
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            if tick == 21 {
+                assert!(game_manager_b.connection_lost);
+            }
+        }
+
+        assert!(!game_manager_b.connection_lost);
+        assert!(!game_manager_b.awaiting_resync);
+        assert_eq!(game_manager_b.requests.GetReceivedSequences(), vec![RequestID { player_id: 1, sequence: 3 }]);
+        assert_eq!(
+            game_manager_a.current_game_state.capture_simulation_state().calculate_checksum(149),
+            game_manager_b.current_game_state.capture_simulation_state().calculate_checksum(149)
+        );
+    }
+
+    #[test]
+    fn game_managers_stay_in_lockstep_over_a_bad_network() {
+        let time = Rc::new(Cell::new(0.0));
+        let conditions = NetworkConditions { latency: 0.1, jitter: 0.08, reorder_chance: 0.3, loss_chance: 0.0 };
+        let mut game_managers = connected_game_managers(2);
+        for game_manager in &mut game_managers {
+            let clock_time = time.clone();
+            let transport = SimulatedTransport::new(game_manager.transport.take().unwrap(), Box::new(move || clock_time.get()), game_manager.local_player_id as u64);
+            transport.conditions().set(conditions);
+            game_manager.transport = Some(Box::new(transport));
+        }
+
+        for tick in 1..400 {
+            time.set(tick as f64 * TICK_DURATION);
+            for (i, game_manager) in game_managers.iter_mut().enumerate() {
+                let player_id = game_manager.local_player_id;
+                let sprite_uuid = if player_id == 1 { UNIT_1 } else { UNIT_2 };
+                //Scheduled only 2 ticks ahead, so most requests arrive late and are rolled back to.
+                let request = if tick == 1 {
+                    let sequence = game_manager.next_request_sequence();
+                    Some(Request::SpriteCreate(SpriteCreateRequest {tick: 3, player_id, sequence, sprite_uuid, position: TilePosition{x: 1 + i as i32, y: 1}, sprite_type: SpriteType::Engineer}))
+                } else if tick < 300 && tick % (7 + i as u32) == 0 {
+                    let sequence = game_manager.next_request_sequence();
+                    Some(Request::SpriteMove(SpriteMoveRequest {tick: tick + 2, player_id, sequence, sprite_uuid, position: TilePosition{x: (tick % 9) as i32 + 1, y: (tick % 5) as i32 + 1}}))
+                } else {
+                    None
+                };
+                if let Some(request) = request {
+                    game_manager.addLocalRequest(request);
+                }
+                game_manager.getNetworkRequests();
+                game_manager.process_tick(tick);
+            }
+        }
+
+        assert!(game_managers.iter().all(|game_manager| !game_manager.awaiting_resync && !game_manager.desync_detector.is_desynchronized()));
+        assert_eq!(game_managers[0].requests.GetReceivedSequences(), game_managers[1].requests.GetReceivedSequences());
+        assert_eq!(
+            game_managers[0].current_game_state.capture_simulation_state().calculate_checksum(399),
+            game_managers[1].current_game_state.capture_simulation_state().calculate_checksum(399)
+        );
+    }
+
+    #[test]
+    fn paired_game_managers_stay_in_lockstep() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        game_manager_b.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 3, player_id: 2, sequence: 1, sprite_uuid: UNIT_2, position: TilePosition{x: 2, y: 2}, sprite_type: SpriteType::Mech}));
+        for tick in 1..100 {
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            if tick == 10 {
+                game_manager_a.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 20, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 4, y: 4}}));
+                game_manager_b.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 20, player_id: 2, sequence: 2, sprite_uuid: UNIT_2, position: TilePosition{x: 5, y: 5}}));
+            }
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+        }
+
+        for uuid in [UNIT_1, UNIT_2] {
+            let tile_pos_a = game_manager_a.current_game_state.sprite_map.get(&uuid).unwrap().get_tile_pos();
+            let tile_pos_b = game_manager_b.current_game_state.sprite_map.get(&uuid).unwrap().get_tile_pos();
+            assert_eq!(tile_pos_a, tile_pos_b);
+        }
+        assert_eq!(game_manager_a.current_game_state.sprite_map.get(&UNIT_1).unwrap().get_tile_pos(), Vec2::new(4.0, 4.0));
+        assert_eq!(game_manager_b.current_game_state.sprite_map.get(&UNIT_2).unwrap().get_tile_pos(), Vec2::new(5.0, 5.0));
+    }
+
+    #[test]
+    fn commands_for_units_owned_by_other_players_are_rejected() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        for tick in 1..100 {
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            if tick == 10 {
+                //Player 2 tries to move player 1's engineer.
+                game_manager_b.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 20, player_id: 2, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}));
+            }
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+        }
+
+        for game_manager in [&mut game_manager_a, &mut game_manager_b] {
+            assert_eq!(game_manager.current_game_state.get_unit_owner(UNIT_1), Some(1));
+            assert_eq!(game_manager.current_game_state.sprite_map.get(&UNIT_1).unwrap().get_tile_pos(), Vec2::new(1.0, 1.0));
+            game_manager.current_game_state.mark_new_selected_sprite(UNIT_1, game_manager.local_player_id);
+        }
+        assert_eq!(game_manager_a.current_game_state.selected_entity, UNIT_1);
+        assert_eq!(game_manager_b.current_game_state.selected_entity, 0);
+    }
+
+    #[test]
+    fn spectator_joins_mid_match_and_cannot_issue_commands() {
+        let room = LoopbackRoom::default();
+        let (transport_a, transport_s) = (room.join(), room.join());
+        let mut game_manager_a = create_headless_game_manager();
+        let mut game_manager_s = create_headless_game_manager();
+        game_manager_a.transport = Some(Box::new(transport_a));
+        game_manager_s.transport = Some(Box::new(transport_s));
+        game_manager_a.local_player_id = 1;
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        game_manager_a.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 90, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 6, y: 6}}));
+        for tick in 1..100 {
+            game_manager_a.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+        }
+
+        game_manager_s.join_as_spectator();
+        for tick in 100..200 {
+            game_manager_a.getNetworkRequests();
+            game_manager_s.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+            //Like the main loop, the spectator's clock jumps forward to the snapshot.
+            game_manager_s.process_tick(tick.max(game_manager_s.last_tick));
+        }
+        assert!(!game_manager_s.awaiting_resync);
+        assert_eq!(
+            game_manager_a.current_game_state.capture_simulation_state().calculate_checksum(199),
+            game_manager_s.current_game_state.capture_simulation_state().calculate_checksum(199)
+        );
+        assert_eq!(game_manager_s.current_game_state.sprite_map.get(&UNIT_1).unwrap().get_tile_pos(), Vec2::new(6.0, 6.0));
+
+        game_manager_s.local_player_id = 1;
+        let unit_position = grid_to_world_coords(Vec2::new(6.0, 6.0)) + Vec2::new(29.0, 29.0);
+        game_manager_s.mouse_clicked(unit_position);
+        assert_eq!(game_manager_s.current_game_state.selected_entity, 0);
+        game_manager_s.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 210, player_id: 1, sequence: 3, sprite_uuid: UNIT_1, position: TilePosition{x: 2, y: 2}}));
+        assert!(game_manager_s.outgoing_messages.is_empty());
+        assert!(game_manager_s.requests.GetRequestsOfParticularTick(210).is_empty());
+    }
+
+    #[test]
+    fn input_delay_follows_measured_round_trip() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+        assert_eq!(game_manager_a.input_delay(), DEFAULT_INPUT_DELAY);
+
+        game_manager_a.update_latency(0.0);
+        game_manager_a.flush_outgoing_messages();
+        game_manager_b.getNetworkRequests();
+        game_manager_b.flush_outgoing_messages();
+        game_manager_a.update_latency(0.12);
+        game_manager_a.getNetworkRequests();
+        assert_eq!(game_manager_a.latency.get_peer_latency(2).map(|latency| latency.last_rtt), Some(0.12));
+        assert_eq!(game_manager_a.input_delay(), 5);
+        assert_eq!(game_manager_b.input_delay(), DEFAULT_INPUT_DELAY);
+
+        let sequence = game_manager_a.next_request_sequence();
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 1, player_id: 1, sequence, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        for tick in 1..=10 {
+            game_manager_a.process_tick(tick);
+        }
+        game_manager_a.current_game_state.mark_new_selected_sprite(UNIT_1, 1);
+        game_manager_a.mouse_clicked(grid_to_world_coords(Vec2::new(6.0, 6.0)) + Vec2::new(29.0, 29.0));
+        assert!(!game_manager_a.requests.GetRequestsOfParticularTick(15).is_empty());
+    }
+
+    #[test]
+    fn late_joining_peer_is_answered_once() {
+        let room = LoopbackRoom::default();
+        let (transport_a, mut transport_b) = (room.join(), room.join());
+        let mut game_manager_a = create_headless_game_manager();
+        game_manager_a.transport = Some(Box::new(transport_a));
+        let local_hello = Hello::new("map".to_string(), 1, "A".to_string(), 1);
+        game_manager_a.local_hello = Some(local_hello.clone());
+        let mut spectator_hello = Hello::new("map".to_string(), 3, "S".to_string(), 3);
+        spectator_hello.role = PeerRole::Spectator;
+        let message = serde_json::to_vec(&HandshakeMessage::Hello(spectator_hello)).unwrap();
+
+        game_manager_a.receive_message(&message);
+        game_manager_a.receive_message(&message);
+        assert_eq!(transport_b.poll_event(), Some(TransportEvent::Connected));
+        let reply = serde_json::to_vec(&HandshakeMessage::Hello(local_hello)).unwrap();
+        assert_eq!(transport_b.poll_event(), Some(TransportEvent::Message(reply)));
+        assert_eq!(transport_b.poll_event(), None);
+        assert_eq!(game_manager_a.peers.len(), 1);
+    }
+
+    #[test]
+    fn diverged_state_is_reported_as_desync() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        game_manager_b.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 3, player_id: 2, sequence: 1, sprite_uuid: UNIT_2, position: TilePosition{x: 2, y: 2}, sprite_type: SpriteType::Mech}));
+        for tick in 1..200 {
+            game_manager_a.getNetworkRequests();
+            game_manager_b.getNetworkRequests();
+            if tick == 100 {
+                //Corrupt peer B's copy of unit 1 without going through a request.
+                if let SpriteID::Engineer(engineer_entity) = game_manager_b.current_game_state.sprite_map.get_mut(&UNIT_1).unwrap() {
+                    engineer_entity.x = 3;
+                }
+            }
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            if tick < 100 {
+                assert!(!game_manager_a.desync_detector.is_desynchronized());
+                assert!(!game_manager_b.desync_detector.is_desynchronized());
+            }
+        }
+
+        for game_manager in [&game_manager_a, &game_manager_b] {
+            let report = game_manager.desync_detector.first_desync.clone().unwrap();
+            assert_eq!(report.tick, 100);
+            assert_eq!(report.differing_units, vec![UNIT_1]);
+        }
+    }
+
+    #[test]
+    fn lagging_peer_resynchronizes_from_snapshot() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        game_manager_a.addLocalRequest(Request::SpriteMove(SpriteMoveRequest {tick: 5, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}));
+        //Peer B does not hear from A until long after the requests were due, further back than B keeps waiting for A.
+        let first_heard_tick = MAX_UNCONFIRMED_HISTORY + 50;
+        for tick in 1..first_heard_tick + 200 {
+            game_manager_a.getNetworkRequests();
+            if tick >= first_heard_tick {
+                game_manager_b.getNetworkRequests();
+            }
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            if tick == first_heard_tick {
+                assert!(game_manager_b.awaiting_resync);
+            }
+        }
+
+        assert!(!game_manager_b.awaiting_resync);
+        assert_eq!(game_manager_a.last_tick, game_manager_b.last_tick);
+        assert!(game_manager_b.confirmed_tick() > first_heard_tick);
+        assert_eq!(
+            game_manager_a.current_game_state.capture_simulation_state().calculate_checksum(game_manager_a.last_tick),
+            game_manager_b.current_game_state.capture_simulation_state().calculate_checksum(game_manager_b.last_tick)
+        );
+        assert_eq!(game_manager_b.current_game_state.sprite_map.get(&UNIT_1).unwrap().get_tile_pos(), Vec2::new(5.0, 5.0));
+    }
+
+    #[test]
+    fn longer_rollback_window_rolls_back_instead_of_resyncing() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+        game_manager_b.rollback_window = 120;
+
+        game_manager_a.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        for tick in 1..300 {
+            game_manager_a.getNetworkRequests();
+            //A 78 tick delay, inside B's window.
+            if tick >= 80 {
+                game_manager_b.getNetworkRequests();
+            }
+            game_manager_a.process_tick(tick);
+            game_manager_b.process_tick(tick);
+            assert!(!game_manager_b.awaiting_resync);
+            assert!(game_manager_b.game_state_history.len() <= 122);
+        }
+        assert!(game_manager_b.current_game_state.sprite_map.contains_key(&UNIT_1));
+
+        //The history only holds the units, the tiles are never rolled back.
+        let simulation_state = game_manager_b.game_state_history.get(&299).unwrap();
+        assert_eq!(simulation_state.units.len(), 1);
+        assert_eq!(simulation_state.units[0].get_uuid(), UNIT_1);
+    }
+
+    #[test]
+    fn three_game_managers_stay_in_lockstep() {
+        let mut game_managers = connected_game_managers(3);
+
+        for tick in 1..300 {
+            for (i, game_manager) in game_managers.iter_mut().enumerate() {
+                let player_id = game_manager.local_player_id;
+                //Each player's requests arrive a different number of ticks late at the others.
+                let request = if tick == 1 {
+                    let sequence = game_manager.next_request_sequence();
+                    let sprite_uuid = game_manager.id_allocator.next_id();
+                    Some(Request::SpriteCreate(SpriteCreateRequest {tick: 1 + i as u32, player_id, sequence, sprite_uuid, position: TilePosition{x: 1 + i as i32, y: 1}, sprite_type: SpriteType::Engineer}))
+                } else if tick < 200 && tick % (5 + i as u32) == 0 {
+                    let sequence = game_manager.next_request_sequence();
+                    let sprite_uuid = [UNIT_1, UNIT_2, UNIT_3][i];
+                    Some(Request::SpriteMove(SpriteMoveRequest {tick: tick + i as u32, player_id, sequence, sprite_uuid, position: TilePosition{x: (tick % 9) as i32 + 1, y: (tick % 5) as i32 + 1}}))
+                } else {
+                    None
+                };
+                if let Some(request) = request {
+                    game_manager.addLocalRequest(request);
+                }
+                game_manager.getNetworkRequests();
+                game_manager.process_tick(tick);
+                //Everything up to the previous tick has been announced by the players that already ran this tick.
+                assert!(game_manager.confirmed_tick() + 2 >= tick);
+            }
+        }
+
+        for game_manager in &game_managers {
+            assert!(!game_manager.awaiting_resync && !game_manager.desync_detector.is_desynchronized());
+            assert_eq!(game_manager.input_tracker.get_all_confirmed_ticks().len(), 2);
+            assert_eq!(game_manager.current_game_state.sprite_uuid_list.len(), game_managers[0].current_game_state.sprite_uuid_list.len());
+            assert_eq!(game_manager.current_game_state.capture_simulation_state().calculate_checksum(299), game_managers[0].current_game_state.capture_simulation_state().calculate_checksum(299));
+        }
+    }
+
+    #[test]
+    fn history_is_kept_until_every_peer_confirms_it() {
+        let mut game_managers = connected_game_managers(2);
+        let mut game_manager_b = game_managers.pop().unwrap();
+        let mut game_manager_a = game_managers.pop().unwrap();
+        //B has joined but not announced anything yet.
+        assert_eq!(game_manager_a.confirmed_tick(), 0);
+
+        for tick in 1..200 {
+            game_manager_a.getNetworkRequests();
+            game_manager_a.process_tick(tick);
+            //B stalls after tick 20, e.g. its window was being dragged.
+            if tick <= 20 {
+                game_manager_b.getNetworkRequests();
+                game_manager_b.process_tick(tick);
+            }
+        }
+        assert_eq!(game_manager_a.confirmed_tick(), 19);
+        assert_eq!(game_manager_a.game_state_history.keys().min(), Some(&19));
+
+        //B catches up and creates a unit for a tick that A simulated long ago, A rolls back instead of resyncing.
+        game_manager_b.addLocalRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 21, player_id: 2, sequence: 1, sprite_uuid: UNIT_2, position: TilePosition{x: 2, y: 2}, sprite_type: SpriteType::Mech}));
+        for tick in 21..200 {
+            game_manager_b.getNetworkRequests();
+            game_manager_b.process_tick(tick);
+        }
+        game_manager_b.flush_outgoing_messages();
+        game_manager_a.getNetworkRequests();
+        game_manager_a.process_tick(200);
+        assert!(!game_manager_a.awaiting_resync);
+        assert!(game_manager_a.current_game_state.sprite_map.contains_key(&UNIT_2));
+        //Only confirmed ticks are compared, so rolling back to B's late request is not mistaken for a desync.
+        assert!(game_manager_a.desync_detector.first_desync.is_none());
+        assert_eq!(game_manager_a.confirmed_tick(), 199);
+        assert_eq!(game_manager_a.game_state_history.len() as Tick, game_manager_a.rollback_window + 2);
+    }
+
+    #[test]
+    fn players_are_corrected_to_the_server_state() {
+        let mut players = connected_game_managers(3);
+        let mut server = players.pop().unwrap();
+        server.is_server = true;
+        server.player_slots.remove(&server.local_player_id);
+        for player in &mut players {
+            player.authority = Some(server.local_player_id);
+            player.peers.retain(|peer| peer.player_id != server.local_player_id);
+            player.player_slots.remove(&server.local_player_id);
+        }
+
+        for tick in 1..300 {
+            for (i, player) in players.iter_mut().enumerate() {
+                let player_id = player.local_player_id;
+                let request = if tick == 1 {
+                    let sequence = player.next_request_sequence();
+                    let sprite_uuid = player.id_allocator.next_id();
+                    Some(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id, sequence, sprite_uuid, position: TilePosition{x: 1 + i as i32, y: 1}, sprite_type: SpriteType::Engineer}))
+                } else if tick < 200 && tick % (6 + i as u32) == 0 {
+                    let sequence = player.next_request_sequence();
+                    let sprite_uuid = [UNIT_1, UNIT_2][i];
+                    Some(Request::SpriteMove(SpriteMoveRequest {tick: tick + 1, player_id, sequence, sprite_uuid, position: TilePosition{x: (tick % 9) as i32 + 1, y: (tick % 5) as i32 + 1}}))
+                } else {
+                    None
+                };
+                if let Some(request) = request {
+                    player.addLocalRequest(request);
+                }
+                if tick == 100 && i == 1 {
+                    //Player 2 teleports player 1's engineer by editing its own state.
+                    if let SpriteID::Engineer(engineer_entity) = player.current_game_state.sprite_map.get_mut(&UNIT_1).unwrap() {
+                        engineer_entity.x = 3;
+                        engineer_entity.y = 8;
+                    }
+                }
+                player.getNetworkRequests();
+                player.process_tick(tick);
+            }
+            server.getNetworkRequests();
+            server.process_tick(tick);
+            server.flush_outgoing_messages();
+        }
+
+        assert_eq!(players[0].corrections, 0);
+        assert!(players[1].corrections >= 1);
+        //The last requests were for tick 200, long enough ago that every one of them has been purged.
+        assert_eq!(server.requests.GetNumberOfRequests(), 0);
+        for player in &players {
+            assert!(!player.awaiting_resync);
+            assert_eq!(player.current_game_state.capture_simulation_state().calculate_checksum(299), server.current_game_state.capture_simulation_state().calculate_checksum(299));
+        }
+    }
+
+    #[test]
+    fn server_state_older_than_the_history_is_ignored() {
+        let mut server = create_headless_game_manager();
+        server.is_server = true;
+        server.addRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        server.addRequest(Request::SpriteMove(SpriteMoveRequest {tick: 50, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}}));
+        for tick in 1..=10 {
+            server.process_tick(tick);
+        }
+        let snapshot = GameStateSnapshot::new(10, server.game_state_history.get(&10).unwrap().clone(), &server.requests);
+        //Requests up to the snapshot's tick are already part of its state.
+        assert!(snapshot.requests == vec![Request::SpriteMove(SpriteMoveRequest {tick: 50, player_id: 1, sequence: 2, sprite_uuid: UNIT_1, position: TilePosition{x: 5, y: 5}})]);
+
+        let mut player = create_headless_game_manager();
+        player.authority = Some(9);
+        for tick in 1..200 {
+            player.process_tick(tick);
+        }
+        player.reconcile(snapshot);
+        assert_eq!(player.corrections, 0);
+        assert_eq!(player.last_tick, 199);
+        assert!(!player.current_game_state.sprite_map.contains_key(&UNIT_1));
+    }
+
+    #[test]
+    fn server_drops_requests_too_late_to_roll_back() {
+        let mut server = create_headless_game_manager();
+        server.is_server = true;
+        for tick in 1..100 {
+            server.process_tick(tick);
+        }
+        let status = server.addNetworkRequest(Request::SpriteCreate(SpriteCreateRequest {tick: 5, player_id: 1, sequence: 1, sprite_uuid: UNIT_1, position: TilePosition{x: 1, y: 1}, sprite_type: SpriteType::Engineer}));
+        assert!(matches!(status, RequestStatus::Desynchronized));
+        assert!(!server.awaiting_resync);
+        assert!(server.outgoing_messages.is_empty());
+        assert!(!server.current_game_state.sprite_map.contains_key(&UNIT_1));
+    }
+
+    #[test]
+    fn unbounded_progress_without_requests_requests_resync() {
+        let mut game_manager = create_headless_game_manager();
+        //Player 2 keeps announcing requests that never arrive.
+        for tick in 1..=MAX_PENDING_PROGRESS as u32 {
+            game_manager.receive_network_message(NetworkMessage::InputProgress(InputProgress { player_id: 2, tick, sequence: tick }));
+        }
+        assert!(!game_manager.awaiting_resync);
+        game_manager.receive_network_message(NetworkMessage::InputProgress(InputProgress { player_id: 2, tick: 2000, sequence: 2000 }));
+        assert!(game_manager.awaiting_resync);
+    }
+}
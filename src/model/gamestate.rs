@@ -1,76 +1,98 @@
 use super::super::sprites::sprite::{ Sprite, SpriteID};
+use super::checksum::{StateChecksum, StateHasher};
+use super::random::SimulationRng;
+use super::requests::PlayerID;
 use crate::Vec2;
+use serde::{Serialize, Deserialize};
+use crate::network::wireformat::de_bin_vec;
+use nanoserde::{SerBin, DeBin, DeBinErr};
 type SpriteMap = std::collections::HashMap<u32, SpriteID>;
 
+/*The part of GameState that changes from tick to tick: the units and the rng. Tiles are static and the selection
+is local, so both are left out. This is what the rollback history and resync snapshots hold.*/
+#[derive(Serialize, Deserialize, SerBin, Clone, Default)]
+pub struct SimulationState {
+    pub units: Vec<SpriteID>, //Sorted by uuid.
+    pub rng: SimulationRng,
+}
+
+//Not derived, the unit count comes from the network, see de_bin_vec.
+impl DeBin for SimulationState {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(SimulationState { units: de_bin_vec(o, d)?, rng: DeBin::de_bin(o, d)? })
+    }
+}
+
+impl SimulationState {
+    pub fn calculate_checksum(&self, tick: u32) -> StateChecksum {
+        calculate_units_checksum(self.units.iter().collect(), &self.rng, tick)
+    }
+}
+
+/*Units are visited in uuid order so the result does not depend on HashMap iteration order.
+Tiles are static and their uuids are generated locally, so they are left out.*/
+fn calculate_units_checksum(mut units: Vec<&SpriteID>, rng: &SimulationRng, tick: u32) -> StateChecksum {
+    units.sort_by_key(|unit| unit.get_uuid());
+    let mut state_hasher = StateHasher::new();
+    rng.hash_state(&mut state_hasher);
+    let mut unit_checksums = Vec::new();
+    for unit in units {
+        let mut unit_hasher = StateHasher::new();
+        match unit {
+            SpriteID::Engineer(engineer_entity) => {
+                unit_hasher.write_u8(0);
+                engineer_entity.hash_state(&mut unit_hasher);
+            }
+            SpriteID::Mech(mech_entity) => {
+                unit_hasher.write_u8(1);
+                mech_entity.hash_state(&mut unit_hasher);
+            }
+            SpriteID::Tile(_tile_entity) => continue,
+        }
+        state_hasher.write_u32(unit.get_uuid());
+        state_hasher.write_u64(unit_hasher.finish());
+        unit_checksums.push((unit.get_uuid(), unit_hasher.finish()));
+    }
+    StateChecksum { tick, checksum: state_hasher.finish(), unit_checksums }
+}
+
 #[derive(Clone, Default)]
 pub struct GameState {
     pub sprite_map: SpriteMap,
     pub sprite_uuid_list: Vec<u32>,
     pub selected_entity: u32,
+    pub rng: SimulationRng, //Seeded from the match seed, see SimulationRng.
 }
 impl GameState {
-    pub fn sort_by_z_index(&mut self) {
-        let mut sprite_uuid_copy = self.sprite_uuid_list.clone();
-        sprite_uuid_copy.sort_by(|a, b| {
-            let ordering = self
-                .sprite_map
-                .get_mut(a)
-                .unwrap()
-                .get_zindex()
-                .cmp(&self.sprite_map.get(b).unwrap().get_zindex());
-            if ordering == std::cmp::Ordering::Equal {
-                let mut a_val = 0;
-                let mut b_val = 0;
-                match self.sprite_map.get(a).unwrap() {
-                    SpriteID::Tile(sprite_move_request) => a_val = 1,
-                    Default => b_val = b_val,
-                }
-                match self.sprite_map.get(b).unwrap() {
-                    SpriteID::Tile(sprite_move_request) => b_val = 1,
-                    Default => b_val = b_val,
-                }
-                return a_val.cmp(&b_val);
-            } else {
-                return ordering;
-            }
-        });
-        self.sprite_uuid_list = sprite_uuid_copy;
-    }
-
-    pub fn render(&mut self) {
-        self.sort_by_z_index();
-        for uuid in &self.sprite_uuid_list {
-            let sprite = self.sprite_map.get_mut(&uuid).unwrap();
-            sprite.draw(); //Draw all sprites in Sprite List
-        }
-    }
     pub fn is_sprite_within_bounds(&mut self, mouse_coords: Vec2) -> Option<u32> {
         let mut selected = 0;
+        let mut selected_zindex = 0;
         for uuid in &self.sprite_uuid_list {
-            let sprite = self.sprite_map.get_mut(&uuid).unwrap();
+            let sprite = self.sprite_map.get_mut(uuid).unwrap();
+            //Prefer the sprite drawn on top when several overlap the click.
             match sprite {
-                SpriteID::Engineer(engineer_entity) => {
-                    if engineer_entity.is_within_bounds(mouse_coords) {
+                SpriteID::Engineer(engineer_entity)
+                    if engineer_entity.is_within_bounds(mouse_coords) && engineer_entity.get_zindex() >= selected_zindex => {
                         selected = engineer_entity.uuid;
+                        selected_zindex = engineer_entity.get_zindex();
                     }
-                }
-                SpriteID::Mech(mech_entity) => {
-                    if mech_entity.is_within_bounds(mouse_coords) {
+                SpriteID::Mech(mech_entity)
+                    if mech_entity.is_within_bounds(mouse_coords) && mech_entity.get_zindex() >= selected_zindex => {
                         selected = mech_entity.uuid;
+                        selected_zindex = mech_entity.get_zindex();
                     }
-                }
                 _default => {}
             };
         }
         if selected != 0 {
             return Some(selected);
         }
-        return None;
+        None
     }
 
-    pub fn process_tick(&mut self, tick: u32) {
+    pub fn process_tick(&mut self, _tick: u32) {
         for uuid in &self.sprite_uuid_list {
-            let sprite = self.sprite_map.get_mut(&uuid).unwrap();
+            let sprite = self.sprite_map.get_mut(uuid).unwrap();
             match sprite {
                 SpriteID::Engineer(engineer_entity) => {
                     engineer_entity.tick(1); //each tick is 20ms
@@ -83,7 +105,55 @@ impl GameState {
         }
     }
 
-    pub fn mark_new_selected_sprite(&mut self, uuid: u32) {
+    pub fn capture_simulation_state(&self) -> SimulationState {
+        let mut units: Vec<SpriteID> = self.sprite_map.values().filter(|sprite| !matches!(sprite, SpriteID::Tile(_))).cloned().collect();
+        units.sort_by_key(|unit| unit.get_uuid());
+        SimulationState { units, rng: self.rng.clone() }
+    }
+
+    //Replaces every unit and the rng with the captured ones, keeping the tiles and, where the unit still exists, the selection.
+    pub fn restore_simulation_state(&mut self, state: &SimulationState) {
+        let sprite_map = &mut self.sprite_map;
+        self.sprite_uuid_list.retain(|uuid| match sprite_map.get(uuid) {
+            Some(SpriteID::Tile(_tile_entity)) => true,
+            _default => {
+                sprite_map.remove(uuid);
+                false
+            }
+        });
+
+        let mut selected_entity_exists = false;
+        for unit in &state.units {
+            let mut unit = unit.clone();
+            match &mut unit {
+                SpriteID::Engineer(engineer_entity) => engineer_entity.selected = engineer_entity.uuid == self.selected_entity,
+                SpriteID::Mech(mech_entity) => mech_entity.selected = mech_entity.uuid == self.selected_entity,
+                SpriteID::Tile(_tile_entity) => continue,
+            }
+            let uuid = unit.get_uuid();
+            selected_entity_exists |= uuid == self.selected_entity;
+            self.sprite_map.insert(uuid, unit);
+            self.sprite_uuid_list.push(uuid);
+        }
+        if !selected_entity_exists {
+            self.selected_entity = 0;
+        }
+    }
+
+    //None for tiles and unknown uuids, which nobody owns.
+    pub fn get_unit_owner(&self, uuid: u32) -> Option<PlayerID> {
+        match self.sprite_map.get(&uuid) {
+            Some(SpriteID::Engineer(engineer_entity)) => Some(engineer_entity.owner),
+            Some(SpriteID::Mech(mech_entity)) => Some(mech_entity.owner),
+            _default => None,
+        }
+    }
+
+    //Players can only select their own units. Clicking someone else's unit leaves the current selection alone.
+    pub fn mark_new_selected_sprite(&mut self, uuid: u32, player_id: PlayerID) {
+        if self.get_unit_owner(uuid) != Some(player_id) {
+            return;
+        }
         if self.selected_entity != 0 {
             let sprite = self.sprite_map.get_mut(&self.selected_entity).unwrap();
             match sprite {
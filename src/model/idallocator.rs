@@ -0,0 +1,56 @@
+/*Hands out sprite uuids. The uuid space is split into blocks of 2^24 ids: block 0 holds the map tiles, and each
+player slot gets its own block after that, so ids allocated by different peers can never collide.*/
+const IDS_PER_BLOCK: u32 = 1 << 24;
+const TILE_ID_BLOCK: u32 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdAllocator {
+    block: u32,
+    next: u32,
+}
+
+impl IdAllocator {
+    pub fn for_tiles() -> Self {
+        IdAllocator { block: TILE_ID_BLOCK, next: 1 } //0 is never used, it means "nothing selected".
+    }
+
+    pub fn for_player_slot(slot: u8) -> Self {
+        assert!((slot as u32) < u32::MAX / IDS_PER_BLOCK);
+        IdAllocator { block: slot as u32 + 1, next: 0 }
+    }
+
+    //The slot of the player that allocated `uuid`, or None for a tile.
+    pub fn player_slot_of(uuid: u32) -> Option<u8> {
+        match uuid / IDS_PER_BLOCK {
+            TILE_ID_BLOCK => None,
+            block => Some((block - 1) as u8),
+        }
+    }
+
+    pub fn next_id(&mut self) -> u32 {
+        assert!(self.next < IDS_PER_BLOCK, "Id block {} is exhausted", self.block);
+        let id = self.block * IDS_PER_BLOCK + self.next;
+        self.next += 1;
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_do_not_overlap_test() {
+        let mut tiles = IdAllocator::for_tiles();
+        let mut player_0 = IdAllocator::for_player_slot(0);
+        let mut player_1 = IdAllocator::for_player_slot(1);
+        assert_eq!(tiles.next_id(), 1);
+        assert_eq!(tiles.next_id(), 2);
+        assert_eq!(player_0.next_id(), IDS_PER_BLOCK);
+        assert_eq!(player_0.next_id(), IDS_PER_BLOCK + 1);
+        assert_eq!(player_1.next_id(), 2 * IDS_PER_BLOCK);
+        assert_eq!(IdAllocator::for_player_slot(254).next_id(), 255 * IDS_PER_BLOCK);
+        assert_eq!(IdAllocator::player_slot_of(2), None);
+        assert_eq!(IdAllocator::player_slot_of(player_1.next_id()), Some(1));
+    }
+}
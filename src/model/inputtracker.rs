@@ -0,0 +1,142 @@
+use super::requests::PlayerID;
+use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
+use std::collections::{BTreeMap, VecDeque};
+
+type Tick = u32;
+
+/*How many announcements of one player may wait on missing requests. Announcements arrive every frame, so this many
+is far longer than a rollback window: the player's requests are not coming and only a resync can catch up.*/
+pub const MAX_PENDING_PROGRESS: usize = 1024;
+
+/*Sent by every player as its simulation advances: it will not issue any more requests for ticks up to `tick`, and
+the last request it has issued so far is `sequence`.*/
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Clone, Copy, PartialEq)]
+pub struct InputProgress {
+    pub player_id: PlayerID,
+    pub tick: Tick,
+    pub sequence: u32,
+}
+
+#[derive(Default)]
+struct PeerInputs {
+    confirmed_tick: Tick, //Every request of this peer for ticks up to here has been received.
+    pending: VecDeque<InputProgress>, //Announced, but still waiting on some of the requests before it.
+}
+
+/*Tracks, per remote player, the last tick for which all of its inputs are known. A tick is confirmed once every
+player has confirmed it: nothing can arrive for it any more, so it will never be rolled back.*/
+#[derive(Default)]
+pub struct InputTracker {
+    peers: BTreeMap<PlayerID, PeerInputs>,
+}
+
+impl InputTracker {
+    /*received_sequence is the sequence up to which we have every request of the announcing player. Returns false
+    when the announcement was dropped because MAX_PENDING_PROGRESS of the player's are already waiting.*/
+    pub fn add_progress(&mut self, progress: InputProgress, received_sequence: u32) -> bool {
+        let peer = self.peers.entry(progress.player_id).or_default();
+        if progress.tick > peer.confirmed_tick {
+            if peer.pending.len() >= MAX_PENDING_PROGRESS {
+                return false;
+            }
+            peer.pending.push_back(progress);
+        }
+        self.update(progress.player_id, received_sequence);
+        true
+    }
+
+    //Call when requests of `player_id` have been received, they may complete an earlier announcement.
+    pub fn update(&mut self, player_id: PlayerID, received_sequence: u32) {
+        if let Some(peer) = self.peers.get_mut(&player_id) {
+            while let Some(progress) = peer.pending.front() {
+                if progress.sequence > received_sequence {
+                    break;
+                }
+                peer.confirmed_tick = peer.confirmed_tick.max(progress.tick);
+                peer.pending.pop_front();
+            }
+        }
+    }
+
+    //After resynchronizing to the snapshot of `tick`, confirmation starts over from there. Later announcements are kept.
+    pub fn reset_to(&mut self, tick: Tick) {
+        for peer in self.peers.values_mut() {
+            peer.confirmed_tick = tick;
+            peer.pending.retain(|progress| progress.tick > tick);
+        }
+    }
+
+    pub fn get_confirmed_tick(&self, player_id: PlayerID) -> Option<Tick> {
+        self.peers.get(&player_id).map(|peer| peer.confirmed_tick)
+    }
+
+    pub fn get_all_confirmed_ticks(&self) -> Vec<(PlayerID, Tick)> {
+        self.peers.iter().map(|(player_id, peer)| (*player_id, peer.confirmed_tick)).collect()
+    }
+
+    //None until some peer has announced its progress.
+    pub fn min_confirmed_tick(&self) -> Option<Tick> {
+        self.peers.values().map(|peer| peer.confirmed_tick).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_confirmed_once_announced_requests_arrive_test() {
+        let mut tracker = InputTracker::default();
+        assert_eq!(tracker.min_confirmed_tick(), None);
+        tracker.add_progress(InputProgress { player_id: 2, tick: 10, sequence: 0 }, 0);
+        assert_eq!(tracker.get_confirmed_tick(2), Some(10));
+
+        //Player 3 has issued 4 requests by tick 12, only 2 have arrived.
+        tracker.add_progress(InputProgress { player_id: 3, tick: 12, sequence: 4 }, 2);
+        tracker.add_progress(InputProgress { player_id: 3, tick: 15, sequence: 5 }, 2);
+        assert_eq!(tracker.get_confirmed_tick(3), Some(0));
+        assert_eq!(tracker.min_confirmed_tick(), Some(0));
+        tracker.update(3, 4);
+        assert_eq!(tracker.get_confirmed_tick(3), Some(12));
+        tracker.update(3, 5);
+        assert_eq!(tracker.get_all_confirmed_ticks(), vec![(2, 10), (3, 15)]);
+        assert_eq!(tracker.min_confirmed_tick(), Some(10));
+
+        //A stale announcement does not move the confirmed tick back.
+        tracker.add_progress(InputProgress { player_id: 3, tick: 11, sequence: 3 }, 5);
+        assert_eq!(tracker.get_confirmed_tick(3), Some(15));
+    }
+
+    #[test]
+    fn pending_announcements_are_capped_test() {
+        let mut tracker = InputTracker::default();
+        for tick in 1..=MAX_PENDING_PROGRESS as u32 {
+            assert!(tracker.add_progress(InputProgress { player_id: 2, tick, sequence: tick }, 0));
+        }
+        assert!(!tracker.add_progress(InputProgress { player_id: 2, tick: 2000, sequence: 2000 }, 0));
+        //A stale announcement is not queued, so it is not refused either.
+        assert!(tracker.add_progress(InputProgress { player_id: 2, tick: 0, sequence: 0 }, 0));
+
+        //Once the requests arrive the queue drains and new announcements are accepted again.
+        tracker.update(2, MAX_PENDING_PROGRESS as u32);
+        assert_eq!(tracker.get_confirmed_tick(2), Some(MAX_PENDING_PROGRESS as u32));
+        assert!(tracker.add_progress(InputProgress { player_id: 2, tick: 2000, sequence: 2000 }, MAX_PENDING_PROGRESS as u32));
+    }
+
+    #[test]
+    fn reset_restarts_confirmation_at_the_snapshot_tick_test() {
+        let mut tracker = InputTracker::default();
+        tracker.add_progress(InputProgress { player_id: 2, tick: 10, sequence: 0 }, 0);
+        tracker.add_progress(InputProgress { player_id: 3, tick: 20, sequence: 4 }, 2);
+        tracker.add_progress(InputProgress { player_id: 3, tick: 60, sequence: 6 }, 2);
+        tracker.reset_to(40);
+        assert_eq!(tracker.get_all_confirmed_ticks(), vec![(2, 40), (3, 40)]);
+
+        //The announcement for tick 20 was dropped, the one after the snapshot still waits for its requests.
+        tracker.update(3, 5);
+        assert_eq!(tracker.get_confirmed_tick(3), Some(40));
+        tracker.update(3, 6);
+        assert_eq!(tracker.get_confirmed_tick(3), Some(60));
+    }
+}
@@ -0,0 +1,52 @@
+use super::checksum::StateChecksum;
+use super::inputtracker::InputProgress;
+use super::requests::{PlayerID, Request, RequestID};
+use super::snapshot::GameStateSnapshot;
+use crate::network::latency::{Ping, Pong};
+use serde::{Serialize, Deserialize};
+use crate::network::wireformat::de_bin_vec;
+use nanoserde::{SerBin, DeBin, DeBinErr};
+
+//Game traffic between peers once the handshake is done. Sent in binary frames, see network::wireformat.
+#[derive(Serialize, Deserialize, SerBin, DeBin, Clone)]
+pub enum NetworkMessage {
+    Request(Request),
+    Checksum(StateChecksum),
+    ResyncRequest, //Sent by a peer that received a request too old to roll back to.
+    Snapshot(GameStateSnapshot),
+    Ping(Ping),
+    Pong(Pong),
+    RetransmitRequest(RetransmitRequest),
+    ReceivedRequests(ReceivedRequests),
+    InputProgress(InputProgress),
+    AuthoritativeState(GameStateSnapshot), //Broadcast by the server in server-authoritative matches, see GameManager::reconcile.
+}
+
+//Asks `player_id` to send its requests with these sequence numbers again, after later ones arrived without them.
+#[derive(Serialize, Deserialize, SerBin, Debug, Clone, PartialEq)]
+pub struct RetransmitRequest {
+    pub player_id: PlayerID,
+    pub sequences: Vec<u32>,
+}
+
+/*Sent after reconnecting: per player, the sequence up to which `sender` has every request. Each player resends its
+own requests after that, and a peer receiving this for the first time answers with its own so both sides catch up.*/
+#[derive(Serialize, Deserialize, SerBin, Debug, Clone, PartialEq)]
+pub struct ReceivedRequests {
+    pub sender: PlayerID,
+    pub received: Vec<RequestID>,
+    pub is_reply: bool,
+}
+
+//The DeBin impls of message types holding a Vec are written out so its length is checked, see wireformat::de_bin_vec.
+impl DeBin for RetransmitRequest {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(RetransmitRequest { player_id: DeBin::de_bin(o, d)?, sequences: de_bin_vec(o, d)? })
+    }
+}
+
+impl DeBin for ReceivedRequests {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(ReceivedRequests { sender: DeBin::de_bin(o, d)?, received: de_bin_vec(o, d)?, is_reply: DeBin::de_bin(o, d)? })
+    }
+}
@@ -0,0 +1,59 @@
+use super::checksum::StateHasher;
+use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
+
+/*Random numbers for anything inside the simulation. Lives in GameState so it is rolled back, checksummed and
+sent in snapshots along with the units, and is seeded from the match seed so every peer draws the same values.
+SplitMix64, chosen because it is tiny and its output is fully specified.
+Nothing in the simulation draws from it yet. It is reserved for future gameplay randomness, and kept in the state
+now so adding some later does not change the snapshot and replay formats or the checksums.*/
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Clone, Default, PartialEq)]
+pub struct SimulationRng {
+    state: u64,
+}
+
+impl SimulationRng {
+    pub fn new(seed: u64) -> Self {
+        SimulationRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    //Returns a value in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_is_reproducible_test() {
+        let mut rng = SimulationRng::new(0);
+        assert_eq!(rng.next_u64(), 0xe220a8397b1dcdaf);
+        let mut rng_a = SimulationRng::new(1234);
+        let mut rng_b = SimulationRng::new(1234);
+        for _ in 0..100 {
+            let value = rng_a.next_f64();
+            assert!((0.0..1.0).contains(&value));
+            assert_eq!(value, rng_b.next_f64());
+        }
+        assert_ne!(SimulationRng::new(1).next_u64(), SimulationRng::new(2).next_u64());
+    }
+}
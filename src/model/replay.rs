@@ -0,0 +1,134 @@
+use super::requests::{PlayerID, Request};
+use crate::network::handshake::BUILD_HASH;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+/*Replay files are JSON lines: a ReplayHeader, then every request in the order it was queued. Every unit is created
+by a request, so the header's match seed, map and player slots plus the requests are enough to re-simulate the whole match.*/
+pub const REPLAY_FORMAT_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayHeader {
+    pub format_version: u32,
+    pub build_hash: String, //Replays are only guaranteed to play back identically on the build that recorded them.
+    pub map_id: String,
+    pub match_seed: u64,
+    pub player_slots: BTreeMap<PlayerID, u8>, //Decides which unit ids each player may create, see GameManager::validate_request.
+}
+
+impl ReplayHeader {
+    pub fn new(map_id: String, match_seed: u64, player_slots: BTreeMap<PlayerID, u8>) -> Self {
+        ReplayHeader { format_version: REPLAY_FORMAT_VERSION, build_hash: BUILD_HASH.to_string(), map_id, match_seed, player_slots }
+    }
+}
+
+pub struct ReplayRecorder {
+    writer: Box<dyn Write>,
+    pub recorded_requests: usize,
+}
+
+impl ReplayRecorder {
+    pub fn new(mut writer: Box<dyn Write>, header: &ReplayHeader) -> Result<Self, String> {
+        ReplayRecorder::write_line(&mut writer, header)?;
+        Ok(ReplayRecorder { writer, recorded_requests: 0 })
+    }
+
+    pub fn create(path: &str, header: &ReplayHeader) -> Result<Self, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let file = std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+            ReplayRecorder::new(Box::new(std::io::BufWriter::new(file)), header)
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = header;
+            Err(format!("Cannot record {}: replays are not supported on web", path))
+        }
+    }
+
+    //Flushed after every request, so a crash still leaves a replay of everything up to it.
+    pub fn record(&mut self, request: &Request) {
+        match ReplayRecorder::write_line(&mut self.writer, request) {
+            Ok(()) => self.recorded_requests += 1,
+            Err(e) => println!("Failed to record request: {}", e),
+        }
+    }
+
+    fn write_line<T: Serialize>(writer: &mut Box<dyn Write>, value: &T) -> Result<(), String> {
+        let line = serde_json::to_string(value).map_err(|e| e.to_string())?;
+        writeln!(writer, "{}", line).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub requests: Vec<Request>,
+}
+
+impl Replay {
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, String> {
+        let mut lines = reader.lines();
+        let header: ReplayHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line.map_err(|e| e.to_string())?).map_err(|e| format!("Invalid replay header: {}", e))?,
+            None => return Err("Replay is empty".to_string()),
+        };
+        if header.format_version != REPLAY_FORMAT_VERSION {
+            return Err(format!("Unsupported replay format version {} (expected {})", header.format_version, REPLAY_FORMAT_VERSION));
+        }
+        let mut requests = Vec::new();
+        for (line_number, line) in lines.enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(request) => requests.push(request),
+                Err(e) => return Err(format!("Invalid request on line {}: {}", line_number + 2, e)),
+            }
+        }
+        Ok(Replay { header, requests })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        Replay::read(std::io::BufReader::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::requests::{RequestImpl, SpriteCreateRequest, SpriteMoveRequest, SpriteType};
+    use crate::pathfinding::pathfinder::TilePosition;
+
+    #[test]
+    fn replay_round_trip_test() {
+        let path = std::env::temp_dir().join(format!("engineers_replay_test_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let header = ReplayHeader::new("tiledmap.json:0000000000000001".to_string(), 77, vec![(1, 0), (2, 1)].into_iter().collect());
+        let mut recorder = ReplayRecorder::create(path, &header).unwrap();
+        recorder.record(&Request::SpriteCreate(SpriteCreateRequest {tick: 3, player_id: 1, sequence: 1, sprite_uuid: 1 << 24, sprite_type: SpriteType::Engineer, position: TilePosition{x: 2, y: 2}}));
+        recorder.record(&Request::SpriteMove(SpriteMoveRequest {tick: 9, player_id: 1, sequence: 2, sprite_uuid: 1 << 24, position: TilePosition{x: 6, y: 4}}));
+        assert_eq!(recorder.recorded_requests, 2);
+
+        //Read back while the recorder is still open, as a crash would leave it.
+        let replay = Replay::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(replay.header, header);
+        assert_eq!(replay.requests.len(), 2);
+        assert_eq!(replay.requests[1].get_tick(), 9);
+    }
+
+    #[test]
+    fn invalid_replay_is_rejected_test() {
+        assert!(Replay::read("".as_bytes()).is_err());
+        let mut header = ReplayHeader::new("map".to_string(), 1, BTreeMap::new());
+        header.format_version = REPLAY_FORMAT_VERSION + 1;
+        assert!(Replay::read(serde_json::to_string(&header).unwrap().as_bytes()).is_err());
+        let replay = format!("{}\nnot a request\n", serde_json::to_string(&ReplayHeader::new("map".to_string(), 1, BTreeMap::new())).unwrap());
+        assert!(Replay::read(replay.as_bytes()).err().unwrap().starts_with("Invalid request on line 2"));
+    }
+}
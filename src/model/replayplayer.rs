@@ -0,0 +1,161 @@
+use super::gamemanager::{GameManager, TICK_DURATION};
+use super::gamestate::GameState;
+use super::replay::Replay;
+use super::requests::{Request, RequestImpl, RequestQueue};
+
+type Tick = u32;
+
+pub const MIN_SPEED: f64 = 0.5;
+pub const MAX_SPEED: f64 = 8.0;
+
+/*Drives a GameManager from a recorded replay instead of the network. Requests are handed to the GameManager just
+before their tick is simulated, so playback never needs to roll back.*/
+pub struct ReplayPlayer {
+    requests: Vec<Request>, //Sorted by tick.
+    next_request: usize,
+    initial_game_state: GameState, //Used to re-simulate from the start when seeking further back than the history.
+    tick_accumulator: f64,
+    pub paused: bool,
+    pub speed: f64,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay, initial_game_state: GameState) -> Self {
+        let mut requests = replay.requests;
+        requests.sort_by_key(|request| request.get_tick());
+        ReplayPlayer { requests, next_request: 0, initial_game_state, tick_accumulator: 0.0, paused: false, speed: 1.0 }
+    }
+
+    pub fn last_request_tick(&self) -> Tick {
+        self.requests.last().map_or(0, |request| request.get_tick())
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    //Advances playback by frame_time seconds of wall clock, scaled by the playback speed.
+    pub fn update(&mut self, game_manager: &mut GameManager, frame_time: f64) {
+        if self.paused {
+            return;
+        }
+        self.tick_accumulator += frame_time * self.speed;
+        while self.tick_accumulator >= TICK_DURATION {
+            self.step(game_manager);
+            self.tick_accumulator -= TICK_DURATION;
+        }
+    }
+
+    pub fn step(&mut self, game_manager: &mut GameManager) {
+        let tick = game_manager.last_tick + 1;
+        while self.next_request < self.requests.len() && self.requests[self.next_request].get_tick() <= tick {
+            game_manager.addNetworkRequest(self.requests[self.next_request]);
+            self.next_request += 1;
+        }
+        game_manager.process_tick(tick);
+    }
+
+    /*Moves playback to just after `tick`. Going back restores the state from game_state_history when it still holds
+    that tick, otherwise the match is re-simulated from the start. Going forward simulates the ticks in between.*/
+    pub fn seek(&mut self, game_manager: &mut GameManager, tick: Tick) {
+        if tick < game_manager.last_tick {
+            match game_manager.game_state_history.get(&tick) {
+                Some(simulation_state) => {
+                    game_manager.current_game_state.restore_simulation_state(simulation_state);
+                    game_manager.game_state_history.retain(|history_tick, _| *history_tick <= tick);
+                    game_manager.last_tick = tick;
+                }
+                None => {
+                    game_manager.current_game_state = self.initial_game_state.clone();
+                    game_manager.game_state_history.clear();
+                    game_manager.game_state_history.insert(0, self.initial_game_state.capture_simulation_state());
+                    game_manager.requests = RequestQueue::default();
+                    game_manager.last_tick = 0;
+                    self.next_request = 0;
+                }
+            }
+            let selected_entity = game_manager.current_game_state.selected_entity;
+            if selected_entity != 0 && game_manager.current_game_state.get_unit_owner(selected_entity).is_none() {
+                game_manager.current_game_state.selected_entity = 0;
+            }
+        }
+        while game_manager.last_tick < tick {
+            self.step(game_manager);
+        }
+        self.tick_accumulator = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::random::SimulationRng;
+    use crate::model::replay::ReplayHeader;
+    use crate::model::requests::{SpriteCreateRequest, SpriteMoveRequest, SpriteType};
+    use crate::pathfinding::pathfinder::{Pathfinder, TilePosition};
+    use crate::tiledmap;
+    use include_dir::include_dir;
+    use include_dir::Dir;
+
+    const UNIT: u32 = 1 << 24;
+
+    fn create_game_manager() -> GameManager {
+        static ASSETS_DIR: Dir = include_dir!("assets");
+        let body = ASSETS_DIR.get_file("tiledmap.json").unwrap().contents_utf8().unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
+        let sprite_map = tiledmap::get_tilemap_spritelist(&map_cast);
+        let sprite_uuid_list: Vec<u32> = sprite_map.keys().cloned().collect();
+        let game_state = GameState{sprite_map, sprite_uuid_list, selected_entity: 0, rng: SimulationRng::new(5)};
+        GameManager::new(game_state, Pathfinder::new(map_cast), None)
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            header: ReplayHeader::new("tiledmap.json".to_string(), 5, vec![(1, 0)].into_iter().collect()),
+            requests: vec![
+                //Recorded in arrival order, which is not tick order.
+                Request::SpriteMove(SpriteMoveRequest {tick: 120, player_id: 1, sequence: 1, sprite_uuid: UNIT, position: TilePosition{x: 2, y: 8}}),
+                Request::SpriteCreate(SpriteCreateRequest {tick: 2, player_id: 1, sequence: 2, sprite_uuid: UNIT, sprite_type: SpriteType::Mech, position: TilePosition{x: 1, y: 1}}),
+                Request::SpriteMove(SpriteMoveRequest {tick: 10, player_id: 1, sequence: 3, sprite_uuid: UNIT, position: TilePosition{x: 7, y: 6}}),
+            ],
+        }
+    }
+
+    //Checksums after every tick of a straight-through simulation of the replay.
+    fn straight_through_checksums(ticks: Tick) -> Vec<u64> {
+        let mut game_manager = create_game_manager();
+        for request in replay().requests {
+            game_manager.addRequest(request);
+        }
+        let mut checksums = vec![game_manager.current_game_state.capture_simulation_state().calculate_checksum(0).checksum];
+        for tick in 1..=ticks {
+            game_manager.process_tick(tick);
+            checksums.push(game_manager.current_game_state.capture_simulation_state().calculate_checksum(tick).checksum);
+        }
+        checksums
+    }
+
+    #[test]
+    fn playback_matches_straight_through_simulation_test() {
+        let expected = straight_through_checksums(300);
+        let mut game_manager = create_game_manager();
+        let mut replay_player = ReplayPlayer::new(replay(), game_manager.current_game_state.clone());
+        assert_eq!(replay_player.last_request_tick(), 120);
+
+        replay_player.set_speed(100.0);
+        assert_eq!(replay_player.speed, MAX_SPEED);
+        replay_player.update(&mut game_manager, 1.0); //8 seconds of match time.
+        assert_eq!(game_manager.last_tick, 160);
+        replay_player.paused = true;
+        replay_player.update(&mut game_manager, 1.0);
+        assert_eq!(game_manager.last_tick, 160);
+        assert_eq!(game_manager.current_game_state.capture_simulation_state().calculate_checksum(160).checksum, expected[160]);
+
+        //Back inside the history, back to before it, then forward again.
+        for tick in [140, 30, 300, 0, 250] {
+            replay_player.seek(&mut game_manager, tick);
+            assert_eq!(game_manager.last_tick, tick);
+            assert_eq!(game_manager.current_game_state.capture_simulation_state().calculate_checksum(tick).checksum, expected[tick as usize], "after seeking to {}", tick);
+        }
+    }
+}
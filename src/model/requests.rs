@@ -1,34 +1,75 @@
 use super::super::pathfinding::pathfinder::TilePosition;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet};
 use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
 
-#[derive(Serialize, Deserialize,Debug, Copy, Clone, Default)]
+pub type PlayerID = u32;
+
+//Identifies a request on every peer. Each player numbers its requests 1, 2, 3... as it issues them.
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestID {
+    pub player_id: PlayerID,
+    pub sequence: u32,
+}
+
+#[derive(Serialize, Deserialize, SerBin, DeBin,Debug, Copy, Clone, Default, PartialEq)]
 pub struct SpriteMoveRequest {
     pub tick: u32,
+    pub player_id: PlayerID, //Issuer, must own the unit for the move to be carried out.
+    pub sequence: u32,
     pub sprite_uuid: u32,
     pub position: TilePosition,
 }
-#[derive(Serialize, Deserialize,Copy, Clone)]
+#[derive(Serialize, Deserialize, SerBin, DeBin,Copy, Clone, PartialEq)]
 pub enum SpriteType {
     Engineer,
     Mech
 }
 
-#[derive(Serialize, Deserialize,Copy, Clone)]
+#[derive(Serialize, Deserialize, SerBin, DeBin,Copy, Clone, PartialEq)]
 pub struct SpriteCreateRequest {
     pub tick: u32,
+    pub player_id: PlayerID, //Issuer, becomes the owner of the new unit.
+    pub sequence: u32,
     pub sprite_uuid: u32,
     pub sprite_type: SpriteType,
     pub position: TilePosition,
 }
 
-#[derive(Serialize, Deserialize,Copy, Clone)]
+#[derive(Serialize, Deserialize, SerBin, DeBin,Copy, Clone, PartialEq)]
 pub enum Request {
     SpriteMove(SpriteMoveRequest),
     SpriteCreate(SpriteCreateRequest),
 }
+/*Why a request was not carried out. Requests are validated when they execute, against the simulated state of
+that tick, so every peer rejects exactly the same requests.*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestError {
+    UnknownUnit(u32),
+    NotOwner { sprite_uuid: u32, owner: PlayerID, player_id: PlayerID },
+    DuplicateUnit(u32),
+    OutOfBounds(TilePosition),
+    ForeignId { sprite_uuid: u32, player_id: PlayerID }, //A new unit's uuid is not from its creator's id block.
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestError::UnknownUnit(sprite_uuid) => write!(f, "Unit {} does not exist", sprite_uuid),
+            RequestError::NotOwner { sprite_uuid, owner, player_id } => {
+                write!(f, "Unit {} belongs to player {}, not player {}", sprite_uuid, owner, player_id)
+            }
+            RequestError::DuplicateUnit(sprite_uuid) => write!(f, "Unit {} already exists", sprite_uuid),
+            RequestError::OutOfBounds(position) => write!(f, "Position ({}, {}) is outside the map", position.x, position.y),
+            RequestError::ForeignId { sprite_uuid, player_id } => write!(f, "Unit id {} is not from player {}'s id block", sprite_uuid, player_id),
+        }
+    }
+}
+
 pub trait RequestImpl {
     fn get_tick(&self) -> u32;
+    fn get_player_id(&self) -> PlayerID;
+    fn get_id(&self) -> RequestID;
 }
 impl RequestImpl for Request {
     fn get_tick(&self) -> u32 {
@@ -37,70 +78,405 @@ impl RequestImpl for Request {
             Request::SpriteCreate(sprite_create_request) => sprite_create_request.tick,
         }
     }
+    fn get_player_id(&self) -> PlayerID {
+        match self {
+            Request::SpriteMove(sprite_move_request) => sprite_move_request.player_id,
+            Request::SpriteCreate(sprite_create_request) => sprite_create_request.player_id,
+        }
+    }
+    fn get_id(&self) -> RequestID {
+        match self {
+            Request::SpriteMove(sprite_move_request) => RequestID { player_id: sprite_move_request.player_id, sequence: sprite_move_request.sequence },
+            Request::SpriteCreate(sprite_create_request) => RequestID { player_id: sprite_create_request.player_id, sequence: sprite_create_request.sequence },
+        }
+    }
+}
+
+/*How far past the last contiguous sequence a player's request may be. A larger gap is not a few lost requests:
+the sender is out of sync with us, and asking for every request in between would be unbounded.*/
+pub const MAX_SEQUENCE_GAP: u32 = 1024;
+
+//The sequence numbers received from one player, kept after the requests themselves are purged.
+#[derive(Clone, Default)]
+struct ReceivedSequences {
+    contiguous: u32, //Every sequence up to and including this one has been received.
+    out_of_order: BTreeSet<u32>, //Received after a gap.
+}
+
+impl ReceivedSequences {
+    //Returns false if the sequence had already been received.
+    fn insert(&mut self, sequence: u32) -> bool {
+        if sequence <= self.contiguous || !self.out_of_order.insert(sequence) {
+            return false;
+        }
+        self.advance_contiguous();
+        true
+    }
+
+    fn is_too_far_ahead(&self, sequence: u32) -> bool {
+        sequence > self.contiguous && sequence - self.contiguous > MAX_SEQUENCE_GAP
+    }
+
+    fn advance_contiguous(&mut self) {
+        while let Some(next) = self.contiguous.checked_add(1) {
+            if !self.out_of_order.remove(&next) {
+                break;
+            }
+            self.contiguous = next;
+        }
+    }
+
+    fn missing(&self) -> Vec<u32> {
+        match self.out_of_order.iter().next_back() {
+            Some(newest) => (self.contiguous + 1..*newest).filter(|sequence| !self.out_of_order.contains(sequence)).collect(),
+            None => Vec::new(),
+        }
+    }
 }
 
+//Requests keyed by tick, each tick's requests kept in execution order as they are added.
 #[derive(Clone, Default)]
 pub struct RequestQueue {
-    requests: Vec<Request>,
+    requests: BTreeMap<u32, Vec<Request>>,
+    ticks_by_id: BTreeMap<RequestID, u32>, //The tick of every queued request, ordered by player then sequence.
+    number_of_requests: usize,
+    received: BTreeMap<PlayerID, ReceivedSequences>,
 }
 
+#[allow(non_snake_case)]
 impl RequestQueue {
-    pub fn AddRequest(&mut self, request: Request) {
-        self.requests.push(request);
-        self.requests.sort_by(|a, b| {
-            RequestQueue::GetTickOfParticularRequest(a)
-                .cmp(&RequestQueue::GetTickOfParticularRequest(b))
-        });
+    //Returns false, without queueing it, if a request with the same id was already added or it is too far ahead.
+    pub fn AddRequest(&mut self, request: Request) -> bool {
+        let id = request.get_id();
+        if self.IsTooFarAhead(id) || !self.received.entry(id.player_id).or_default().insert(id.sequence) {
+            return false;
+        }
+        let tick_requests = self.requests.entry(request.get_tick()).or_default();
+        let execution_order = RequestQueue::GetExecutionOrder(&request);
+        let index = tick_requests.partition_point(|queued| RequestQueue::GetExecutionOrder(queued) <= execution_order);
+        tick_requests.insert(index, request);
+        self.ticks_by_id.insert(id, request.get_tick());
+        self.number_of_requests += 1;
+        true
     }
 
-    pub fn GetTickOfParticularRequest(request: &Request) -> u32 {
-        return request.get_tick();
+    /*Requests of the same tick can arrive in any order, so they are executed in an order every peer agrees on:
+    by player, then in the order that player issued them.*/
+    fn GetExecutionOrder(request: &Request) -> RequestID {
+        request.get_id()
     }
+
     pub fn GetRequestsOfParticularTick(&self, tick: u32) -> Vec<Request> {
-        let mut requests = Vec::new();
-        for request in &self.requests {
-            if (request.get_tick() == tick) {
-                requests.push(request.clone())
-            }
+        match self.requests.get(&tick) {
+            Some(tick_requests) => tick_requests.clone(),
+            None => Vec::new(),
         }
-        return requests;
+    }
+    pub fn GetRequestsAfterTick(&self, tick: u32) -> Vec<Request> {
+        use std::ops::Bound::{Excluded, Unbounded};
+        self.requests.range((Excluded(tick), Unbounded)).flat_map(|(_tick, tick_requests)| tick_requests.iter().cloned()).collect()
     }
     pub fn PurgeRequestsOlderThanTick(&mut self, tick: u32) {
-        self.requests=self.requests.clone().into_iter().filter(|w| w.get_tick()>=tick).collect::<Vec<_>>();
+        let newer_requests = self.requests.split_off(&tick);
+        for purged_requests in self.requests.values() {
+            self.number_of_requests -= purged_requests.len();
+            for request in purged_requests {
+                self.ticks_by_id.remove(&request.get_id());
+            }
+        }
+        self.requests = newer_requests;
     }
 
     pub fn GetNumberOfRequests(&self) -> usize {
-        return self.requests.len();
+        self.number_of_requests
+    }
+
+    //Only finds requests that have not been purged yet.
+    pub fn GetRequest(&self, id: RequestID) -> Option<Request> {
+        let tick_requests = self.requests.get(self.ticks_by_id.get(&id)?)?;
+        //Each tick's requests are sorted by id, see GetExecutionOrder.
+        let index = tick_requests.binary_search_by_key(&id, |request| request.get_id()).ok()?;
+        Some(tick_requests[index])
+    }
+
+    //Requests of `player_id` still queued that were issued after `sequence`, in the order they were issued.
+    pub fn GetRequestsOfPlayerAfter(&self, player_id: PlayerID, sequence: u32) -> Vec<Request> {
+        if sequence == u32::MAX {
+            return Vec::new();
+        }
+        let first = RequestID { player_id, sequence: sequence + 1 };
+        let last = RequestID { player_id, sequence: u32::MAX };
+        self.ticks_by_id.range(first..=last).filter_map(|(id, _tick)| self.GetRequest(*id)).collect()
+    }
+
+    //Sequences skipped over by a later request of the same player, e.g. lost during a reconnect.
+    pub fn GetMissingRequests(&self) -> Vec<RequestID> {
+        let mut missing = Vec::new();
+        for (player_id, received) in &self.received {
+            missing.extend(received.missing().into_iter().map(|sequence| RequestID { player_id: *player_id, sequence }));
+        }
+        missing
+    }
+
+    //More than MAX_SEQUENCE_GAP requests of the player are missing before this one.
+    pub fn IsTooFarAhead(&self, id: RequestID) -> bool {
+        self.received.get(&id.player_id).map_or(id.sequence > MAX_SEQUENCE_GAP, |received| received.is_too_far_ahead(id.sequence))
+    }
+
+    pub fn GetReceivedSequence(&self, player_id: PlayerID) -> u32 {
+        self.received.get(&player_id).map_or(0, |received| received.contiguous)
+    }
+
+    //Per player, the sequence up to which every request has been received.
+    pub fn GetReceivedSequences(&self) -> Vec<RequestID> {
+        self.received.iter().map(|(player_id, received)| RequestID { player_id: *player_id, sequence: received.contiguous }).collect()
+    }
+
+    /*Marks every sequence up to `id` as received without queueing anything, for requests that were executed and
+    purged before we joined, as a snapshot reports them.*/
+    pub fn MarkReceivedUpTo(&mut self, id: RequestID) {
+        let received = self.received.entry(id.player_id).or_default();
+        if id.sequence > received.contiguous {
+            received.contiguous = id.sequence;
+            received.out_of_order = match id.sequence.checked_add(1) {
+                Some(next) => received.out_of_order.split_off(&next),
+                None => BTreeSet::new(),
+            };
+            received.advance_contiguous();
+        }
     }
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod tests {
     use super::*;
+    use std::time::{Duration, Instant};
     #[test]
     fn TestRequestSorting() {
         let mut request_queue: RequestQueue = RequestQueue::default();
         let request_1: Request = Request::SpriteMove(SpriteMoveRequest {
             tick: 13,
+            player_id: 1,
+            sequence: 1,
             sprite_uuid: 12,
             position: TilePosition { x: 0, y: 0 },
         });
         let request_2: Request = Request::SpriteMove(SpriteMoveRequest {
             tick: 17,
+            player_id: 2,
+            sequence: 1,
             sprite_uuid: 113232,
             position: TilePosition { x: 0, y: 0 },
         });
         request_queue.AddRequest(request_2);
-        assert_eq!(request_queue.GetNumberOfRequests() == 1, true);
+        assert_eq!(request_queue.GetNumberOfRequests(), 1);
         request_queue.AddRequest(request_1);
         let mut returned_requests=request_queue.GetRequestsOfParticularTick(request_1.get_tick());
-        assert_eq!(returned_requests.len() == 1, true);
-        assert_eq!(returned_requests[0].get_tick() == 13, true);
-        assert_eq!(request_queue.GetNumberOfRequests() == 2, true);
+        assert_eq!(returned_requests.len(), 1);
+        assert_eq!(returned_requests[0].get_tick(), 13);
+        assert_eq!(request_queue.GetNumberOfRequests(), 2);
         request_queue.PurgeRequestsOlderThanTick(14);
-        assert_eq!(request_queue.GetNumberOfRequests() == 1, true);
+        assert_eq!(request_queue.GetNumberOfRequests(), 1);
         returned_requests=request_queue.GetRequestsOfParticularTick(request_2.get_tick());
-        assert_eq!(returned_requests.len() == 1, true);
-        assert_eq!(returned_requests[0].get_tick() == request_2.get_tick(), true);
+        assert_eq!(returned_requests.len(), 1);
+        assert_eq!(returned_requests[0].get_tick(), request_2.get_tick());
+    }
+
+    #[test]
+    fn TestSameTickOrderIgnoresArrivalOrder() {
+        let create: Request = Request::SpriteCreate(SpriteCreateRequest {
+            tick: 5,
+            player_id: 2,
+            sequence: 1,
+            sprite_uuid: 8,
+            sprite_type: SpriteType::Mech,
+            position: TilePosition { x: 1, y: 1 },
+        });
+        let move_1: Request = Request::SpriteMove(SpriteMoveRequest { tick: 5, player_id: 2, sequence: 2, sprite_uuid: 8, position: TilePosition { x: 3, y: 3 } });
+        let move_2: Request = Request::SpriteMove(SpriteMoveRequest { tick: 5, player_id: 1, sequence: 1, sprite_uuid: 4, position: TilePosition { x: 2, y: 2 } });
+        let mut request_queue_a: RequestQueue = RequestQueue::default();
+        let mut request_queue_b: RequestQueue = RequestQueue::default();
+        for request in [move_1, create, move_2] {
+            request_queue_a.AddRequest(request);
+        }
+        for request in [move_2, move_1, create] {
+            request_queue_b.AddRequest(request);
+        }
+        let requests_a = request_queue_a.GetRequestsOfParticularTick(5);
+        assert!(requests_a == request_queue_b.GetRequestsOfParticularTick(5));
+        assert!(requests_a == vec![move_2, create, move_1]);
+    }
+
+    #[test]
+    fn TestDuplicatesAreDroppedAndGapsReported() {
+        let request = |player_id: PlayerID, sequence: u32| Request::SpriteMove(SpriteMoveRequest { tick: 5 + sequence, player_id, sequence, sprite_uuid: 4, position: TilePosition { x: 2, y: 2 } });
+        let mut request_queue: RequestQueue = RequestQueue::default();
+        assert!(request_queue.AddRequest(request(1, 1)));
+        assert!(!request_queue.AddRequest(request(1, 1)));
+        assert!(request_queue.AddRequest(request(1, 4)));
+        assert!(request_queue.AddRequest(request(2, 2)));
+        assert_eq!(request_queue.GetNumberOfRequests(), 3);
+        assert_eq!(
+            request_queue.GetMissingRequests(),
+            vec![RequestID { player_id: 1, sequence: 2 }, RequestID { player_id: 1, sequence: 3 }, RequestID { player_id: 2, sequence: 1 }]
+        );
+        assert!(request_queue.AddRequest(request(1, 3)));
+        assert!(request_queue.GetRequest(RequestID { player_id: 1, sequence: 3 }) == Some(request(1, 3)));
+
+        //Purged requests are still known, so a late duplicate is not executed again.
+        request_queue.PurgeRequestsOlderThanTick(100);
+        assert!(!request_queue.AddRequest(request(1, 4)));
+        request_queue.MarkReceivedUpTo(RequestID { player_id: 2, sequence: 1 });
+        assert_eq!(request_queue.GetMissingRequests(), vec![RequestID { player_id: 1, sequence: 2 }]);
+        assert!(request_queue.GetReceivedSequences() == vec![RequestID { player_id: 1, sequence: 1 }, RequestID { player_id: 2, sequence: 2 }]);
+    }
+
+    #[test]
+    fn TestSequencesTooFarAheadAreIgnored() {
+        let request = |sequence: u32| Request::SpriteMove(SpriteMoveRequest { tick: 5, player_id: 1, sequence, sprite_uuid: 4, position: TilePosition { x: 2, y: 2 } });
+        let mut request_queue: RequestQueue = RequestQueue::default();
+        assert!(!request_queue.AddRequest(request(u32::MAX)));
+        assert!(!request_queue.AddRequest(request(MAX_SEQUENCE_GAP + 1)));
+        assert!(request_queue.AddRequest(request(MAX_SEQUENCE_GAP)));
+        assert_eq!(request_queue.GetNumberOfRequests(), 1);
+        assert_eq!(request_queue.GetMissingRequests().len() as u32, MAX_SEQUENCE_GAP - 1);
+        //The allowed gap is counted from the last contiguous sequence.
+        assert!(request_queue.AddRequest(request(1)));
+        assert!(!request_queue.IsTooFarAhead(request(MAX_SEQUENCE_GAP + 1).get_id()));
+        assert!(request_queue.IsTooFarAhead(request(MAX_SEQUENCE_GAP + 2).get_id()));
+
+        //A snapshot can move the player all the way to the last sequence.
+        request_queue.MarkReceivedUpTo(RequestID { player_id: 1, sequence: u32::MAX });
+        assert_eq!(request_queue.GetReceivedSequence(1), u32::MAX);
+        assert!(request_queue.GetMissingRequests().is_empty());
+        assert!(!request_queue.AddRequest(request(u32::MAX)));
+    }
+
+    //A few thousand requests of four players spread over a rollback window, in a scrambled tick order.
+    fn ScrambledRequests(number_of_requests: u32) -> Vec<Request> {
+        let mut sequences = [0; 4];
+        (0..number_of_requests)
+            .map(|i| {
+                let scrambled = i.wrapping_mul(2654435761);
+                let player_id = scrambled % 4;
+                sequences[player_id as usize] += 1;
+                Request::SpriteMove(SpriteMoveRequest {
+                    tick: scrambled % 50,
+                    player_id,
+                    sequence: sequences[player_id as usize],
+                    sprite_uuid: i,
+                    position: TilePosition { x: (i % 10) as i32, y: (i % 7) as i32 },
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn TestQueueOrderWithThousandsOfRequests() {
+        let requests = ScrambledRequests(5000);
+        let mut request_queue: RequestQueue = RequestQueue::default();
+        for request in &requests {
+            request_queue.AddRequest(*request);
+        }
+        assert_eq!(request_queue.GetNumberOfRequests(), 5000);
+        let mut expected = requests.clone();
+        expected.sort_by_key(|request| (request.get_tick(), RequestQueue::GetExecutionOrder(request)));
+        assert!((0..50).flat_map(|tick| request_queue.GetRequestsOfParticularTick(tick)).collect::<Vec<Request>>() == expected);
+        assert!(request_queue.GetRequest(requests[1234].get_id()) == Some(requests[1234]));
+
+        request_queue.PurgeRequestsOlderThanTick(25);
+        assert_eq!(request_queue.GetNumberOfRequests(), expected.iter().filter(|request| request.get_tick() >= 25).count());
+        assert_eq!(request_queue.GetRequestsOfParticularTick(24).len(), 0);
+        assert!(request_queue.GetRequestsOfParticularTick(30).iter().all(|request| request.get_tick() == 30));
+    }
+
+    //Spreads the scrambled requests over a match, each batch issued during its own tick for up to 50 ticks ahead.
+    fn BusyMatchRequests(number_of_requests: u32, requests_per_tick: usize) -> Vec<Request> {
+        let mut requests = ScrambledRequests(number_of_requests);
+        for (i, request) in requests.iter_mut().enumerate() {
+            if let Request::SpriteMove(sprite_move) = request {
+                sprite_move.tick += (i / requests_per_tick) as u32;
+            }
+        }
+        requests
+    }
+
+    /*Simulates a busy match: every tick a batch of requests is queued, the tick's requests are looked up and the
+    requests older than the rollback window are purged. The lookups by id have to agree with the requests' ticks.*/
+    #[test]
+    fn TestBusyMatchKeepsTheRollbackWindow() {
+        let requests = BusyMatchRequests(20_000, 100);
+        let number_of_ticks = (requests.len() / 100) as u32;
+        let mut request_queue: RequestQueue = RequestQueue::default();
+        let mut looked_up = 0;
+        for (tick, batch_requests) in (0..).zip(requests.chunks(100)) {
+            for request in batch_requests {
+                request_queue.AddRequest(*request);
+            }
+            looked_up += request_queue.GetRequestsOfParticularTick(tick).len();
+            if tick > 47 {
+                request_queue.PurgeRequestsOlderThanTick(tick - 47);
+            }
+        }
+        assert_eq!(looked_up, requests.iter().filter(|request| request.get_tick() < number_of_ticks).count());
+
+        let queued: BTreeSet<RequestID> = requests.iter().filter(|request| request.get_tick() >= number_of_ticks - 1 - 47).map(|request| request.get_id()).collect();
+        assert_eq!(request_queue.GetNumberOfRequests(), queued.len());
+        for request in &requests {
+            let expected = if queued.contains(&request.get_id()) { Some(*request) } else { None };
+            assert!(request_queue.GetRequest(request.get_id()) == expected);
+        }
+        for player_id in 0..4 {
+            let mut expected: Vec<Request> = requests.iter().filter(|request| queued.contains(&request.get_id()) && request.get_player_id() == player_id && request.get_id().sequence > 4_000).cloned().collect();
+            expected.sort_by_key(|request| request.get_id());
+            assert!(request_queue.GetRequestsOfPlayerAfter(player_id, 4_000) == expected);
+        }
+        assert!(request_queue.GetRequestsOfPlayerAfter(0, u32::MAX).is_empty());
+    }
+
+    /*Run with: cargo test --release -- --ignored --nocapture BenchRequestQueue
+    The busy match above at twice the rate, which keeps over ten thousand requests queued. Besides the tick's
+    requests, every tenth new request is looked up by id, as when answering a retransmission request.*/
+    #[test]
+    #[ignore]
+    fn BenchRequestQueue() {
+        let requests_per_tick = 200;
+        let requests = BusyMatchRequests(200_000, requests_per_tick);
+        let mut request_queue: RequestQueue = RequestQueue::default();
+        let (mut insert_time, mut tick_lookup_time, mut id_lookup_time, mut purge_time) = (Duration::ZERO, Duration::ZERO, Duration::ZERO, Duration::ZERO);
+        let (mut id_lookups, mut purges, mut most_queued) = (0, 0, 0);
+        for (tick, batch_requests) in (0..).zip(requests.chunks(requests_per_tick)) {
+            let start = Instant::now();
+            for request in batch_requests {
+                request_queue.AddRequest(*request);
+            }
+            insert_time += start.elapsed();
+            most_queued = most_queued.max(request_queue.GetNumberOfRequests());
+
+            let start = Instant::now();
+            std::hint::black_box(request_queue.GetRequestsOfParticularTick(tick));
+            tick_lookup_time += start.elapsed();
+
+            let start = Instant::now();
+            for request in batch_requests.iter().step_by(10) {
+                std::hint::black_box(request_queue.GetRequest(request.get_id()));
+                id_lookups += 1;
+            }
+            id_lookup_time += start.elapsed();
+
+            if tick > 47 {
+                let start = Instant::now();
+                request_queue.PurgeRequestsOlderThanTick(tick - 47);
+                purge_time += start.elapsed();
+                purges += 1;
+            }
+        }
+        let ticks = (requests.len() / requests_per_tick) as u32;
+        println!("{} requests over {} ticks, up to {} queued at once", requests.len(), ticks, most_queued);
+        println!("Insert: {:?} per request", insert_time / requests.len() as u32);
+        println!("Lookup by tick: {:?} per tick", tick_lookup_time / ticks);
+        println!("Lookup by id: {:?} per request", id_lookup_time / id_lookups);
+        println!("Purge: {:?} per tick", purge_time / purges);
     }
 }
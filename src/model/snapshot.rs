@@ -0,0 +1,43 @@
+use super::gamestate::{GameState, SimulationState};
+use super::requests::{Request, RequestID, RequestQueue};
+use serde::{Serialize, Deserialize};
+use crate::network::wireformat::de_bin_vec;
+use nanoserde::{SerBin, DeBin, DeBinErr};
+
+/*Everything a peer needs to rejoin lockstep: the simulation state as it was after `tick`, and the queued requests
+for the ticks after it. Tiles are static and rebuilt locally from the map, so they are not sent.
+received_sequences tells the peer which older requests were already executed, so it does not ask for them again.*/
+#[derive(Serialize, Deserialize, SerBin, Clone)]
+pub struct GameStateSnapshot {
+    pub tick: u32,
+    pub state: SimulationState,
+    pub requests: Vec<Request>,
+    pub received_sequences: Vec<RequestID>,
+}
+
+//Snapshots come from other peers, so the lengths of both lists are checked before anything is allocated for them.
+impl DeBin for GameStateSnapshot {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(GameStateSnapshot {
+            tick: DeBin::de_bin(o, d)?,
+            state: DeBin::de_bin(o, d)?,
+            requests: de_bin_vec(o, d)?,
+            received_sequences: de_bin_vec(o, d)?,
+        })
+    }
+}
+
+impl GameStateSnapshot {
+    pub fn capture(game_state: &GameState, tick: u32, requests: &RequestQueue) -> Self {
+        GameStateSnapshot::new(tick, game_state.capture_simulation_state(), requests)
+    }
+
+    pub fn new(tick: u32, state: SimulationState, requests: &RequestQueue) -> Self {
+        GameStateSnapshot {
+            tick,
+            state,
+            requests: requests.GetRequestsAfterTick(tick),
+            received_sequences: requests.GetReceivedSequences(),
+        }
+    }
+}
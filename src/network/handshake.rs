@@ -0,0 +1,440 @@
+use super::lobby::LobbyMessage;
+use super::transport::{Transport, TransportEvent};
+use super::wireformat;
+use crate::model::checksum::StateHasher;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+//Bump whenever the handshake or the game protocol changes meaning. Binary layout changes bump WIRE_FORMAT_VERSION.
+pub const PROTOCOL_VERSION: u32 = 9;
+//Set by build.rs from the simulation and protocol sources, so peers whose builds could desync refuse to play together.
+pub const BUILD_HASH: &str = env!("ENGINEERS_BUILD_HASH");
+
+const HELLO_RESEND_INTERVAL: f64 = 0.5;
+//Player slots also pick the unit id block, see IdAllocator.
+pub const MAX_PLAYERS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PeerRole {
+    Player,
+    Spectator, //Receives the request stream and renders the match, but never issues requests.
+    Server, //Headless authoritative server, its state overrides the players' own simulations.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub build_hash: String,
+    pub map_id: String,
+    pub player_id: u32,
+    pub player_name: String,
+    pub seed: u64, //This peer's contribution to the match seed.
+    pub role: PeerRole,
+    pub slot: Option<u8>, //Picked in the lobby. Without a lobby, slots go by player id, see player_slot.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum HandshakeRejection {
+    ProtocolVersionMismatch { local: u32, remote: u32 },
+    BuildMismatch { local: String, remote: String },
+    MapMismatch { local: String, remote: String },
+    DuplicatePlayerId(u32),
+}
+
+impl std::fmt::Display for HandshakeRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HandshakeRejection::ProtocolVersionMismatch { local, remote } => {
+                write!(f, "Protocol version mismatch (local {}, remote {})", local, remote)
+            }
+            HandshakeRejection::BuildMismatch { local, remote } => {
+                write!(f, "Game build mismatch (local {}, remote {})", local, remote)
+            }
+            HandshakeRejection::MapMismatch { local, remote } => {
+                write!(f, "Map mismatch (local {}, remote {})", local, remote)
+            }
+            HandshakeRejection::DuplicatePlayerId(player_id) => write!(f, "Both peers picked player id {}", player_id),
+        }
+    }
+}
+
+impl Hello {
+    pub fn new(map_id: String, player_id: u32, player_name: String, seed: u64) -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            build_hash: BUILD_HASH.to_string(),
+            map_id,
+            player_id,
+            player_name,
+            seed,
+            role: PeerRole::Player,
+            slot: None,
+        }
+    }
+
+    pub fn check_compatible(&self, remote: &Hello) -> Result<(), HandshakeRejection> {
+        if self.protocol_version != remote.protocol_version {
+            return Err(HandshakeRejection::ProtocolVersionMismatch { local: self.protocol_version, remote: remote.protocol_version });
+        }
+        if self.build_hash != remote.build_hash {
+            return Err(HandshakeRejection::BuildMismatch { local: self.build_hash.clone(), remote: remote.build_hash.clone() });
+        }
+        if self.map_id != remote.map_id {
+            return Err(HandshakeRejection::MapMismatch { local: self.map_id.clone(), remote: remote.map_id.clone() });
+        }
+        if self.player_id == remote.player_id {
+            return Err(HandshakeRejection::DuplicatePlayerId(self.player_id));
+        }
+        Ok(())
+    }
+
+    //Every player combines the players' hellos in player id order, so they all arrive at the same seed.
+    pub fn agree_match_seed(&self, remotes: &[Hello]) -> u64 {
+        let mut players: Vec<&Hello> = std::iter::once(self).chain(remotes.iter()).filter(|hello| hello.role == PeerRole::Player).collect();
+        players.sort_by_key(|hello| hello.player_id);
+        let mut hasher = StateHasher::new();
+        for player in players {
+            hasher.write_u64(player.seed);
+        }
+        hasher.finish()
+    }
+
+    //The slot picked in the lobby, or else the place in player id order. Offline, with no remotes, this is slot 0.
+    pub fn player_slot(&self, remotes: &[Hello]) -> u8 {
+        match self.slot {
+            Some(slot) => slot,
+            None => remotes.iter().filter(|remote| remote.role == PeerRole::Player && remote.player_id < self.player_id).count() as u8,
+        }
+    }
+
+    //The slot of every player among us and the remotes. Spectators and the server have none.
+    pub fn player_slots(&self, remotes: &[Hello]) -> BTreeMap<u32, u8> {
+        let players: Vec<Hello> = std::iter::once(self).chain(remotes.iter()).filter(|hello| hello.role == PeerRole::Player).cloned().collect();
+        players.iter().map(|hello| (hello.player_id, hello.player_slot(&players))).collect()
+    }
+}
+
+//Handshake traffic stays JSON so that peers with an incompatible wire format can still tell each other why.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum HandshakeMessage {
+    Hello(Hello),
+    HelloRejected { player_id: u32, rejection: HandshakeRejection }, //Addressed to the peer being turned away.
+    Lobby(LobbyMessage), //Only before the handshake, see network::lobby.
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeState {
+    Pending,
+    Complete(Vec<Hello>), //Holds the remote players' hellos, and the server's if there is one.
+    Rejected(HandshakeRejection),
+}
+
+/*Runs before the GameManager exists. Our hello is resent until the hellos of all the other `players` have arrived,
+and sent once more whenever a new one arrives in case that player connected after our earlier ones went out.
+A spectator only waits for the first player, a server waits for all `players`, and a player that `expects_server`
+also waits for the server. Any other message received in the meantime, including spectators' hellos, is kept so it
+can be handed to the GameManager instead of being lost.
+An incompatible peer is told why and then ignored, the players we are waiting for may still arrive. Its rejection
+of us is only honoured while no compatible peer has answered: two peers on their own cannot tell which of them is
+out of date, but a room that agrees with us should not be broken up by one stale client.*/
+pub struct Handshake {
+    pub local_hello: Hello,
+    pub players: usize, //Including us, unless we are the server.
+    pub expects_server: bool, //The match is played against an authoritative server rather than in lockstep.
+    pub state: HandshakeState,
+    pub pending_messages: Vec<Vec<u8>>,
+    remote_hellos: Vec<Hello>,
+    rejected_hellos: Vec<Hello>,
+    last_sent_time: Option<f64>,
+}
+
+impl Handshake {
+    pub fn new(local_hello: Hello, players: usize) -> Self {
+        Handshake {
+            local_hello,
+            players,
+            expects_server: false,
+            state: HandshakeState::Pending,
+            pending_messages: Vec::new(),
+            remote_hellos: Vec::new(),
+            rejected_hellos: Vec::new(),
+            last_sent_time: None,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.local_hello.role {
+            PeerRole::Player => {
+                let has_server = self.remote_hellos.iter().any(|hello| hello.role == PeerRole::Server);
+                self.remote_players() + 1 >= self.players && (has_server || !self.expects_server)
+            }
+            PeerRole::Spectator => !self.remote_hellos.is_empty(),
+            PeerRole::Server => self.remote_players() >= self.players,
+        }
+    }
+
+    fn remote_players(&self) -> usize {
+        self.remote_hellos.iter().filter(|hello| hello.role == PeerRole::Player).count()
+    }
+
+    pub fn update(&mut self, transport: &mut dyn Transport, time: f64) -> &HandshakeState {
+        if self.state != HandshakeState::Pending {
+            return &self.state;
+        }
+        let resend_due = match self.last_sent_time {
+            Some(last_sent_time) => time - last_sent_time >= HELLO_RESEND_INTERVAL,
+            None => true,
+        };
+        if resend_due && transport.is_connected() {
+            self.send_hello(transport);
+            self.last_sent_time = Some(time);
+        }
+        while let Some(event) = transport.poll_event() {
+            match event {
+                TransportEvent::Message(message) => self.handle_message(transport, message),
+                event => println!("Handshake: {:?}", event),
+            }
+        }
+        &self.state
+    }
+
+    fn send_hello(&self, transport: &mut dyn Transport) {
+        transport.send_text(serde_json::to_string(&HandshakeMessage::Hello(self.local_hello.clone())).unwrap().as_str());
+    }
+
+    //Also fed the messages that arrived while we were still in the lobby.
+    pub fn handle_message(&mut self, transport: &mut dyn Transport, message: Vec<u8>) {
+        if self.state != HandshakeState::Pending || wireformat::is_frame(&message) {
+            self.pending_messages.push(message);
+            return;
+        }
+        match serde_json::from_slice(&message) {
+            Ok(HandshakeMessage::Hello(remote_hello)) if remote_hello.role == PeerRole::Spectator => self.pending_messages.push(message),
+            //A player we already know resending its hello.
+            Ok(HandshakeMessage::Hello(remote_hello)) if self.remote_hellos.contains(&remote_hello) || self.rejected_hellos.contains(&remote_hello) => {}
+            Ok(HandshakeMessage::Hello(remote_hello)) => match self.local_hello.check_compatible(&remote_hello) {
+                Ok(()) if self.remote_hellos.iter().any(|known| known.player_id == remote_hello.player_id) => {
+                    let rejection = HandshakeRejection::DuplicatePlayerId(remote_hello.player_id);
+                    println!("Ignoring peer: {}", rejection);
+                }
+                Ok(()) => {
+                    println!("Handshake with {} (player {})", remote_hello.player_name, remote_hello.player_id);
+                    self.send_hello(transport);
+                    self.remote_hellos.push(remote_hello);
+                    if self.is_complete() {
+                        println!("Handshake complete with {} peers", self.remote_hellos.len() + 1);
+                        self.state = HandshakeState::Complete(self.remote_hellos.clone());
+                    }
+                }
+                Err(rejection) => {
+                    println!("Rejecting peer {}: {}", remote_hello.player_id, rejection);
+                    let message = HandshakeMessage::HelloRejected { player_id: remote_hello.player_id, rejection };
+                    transport.send_text(serde_json::to_string(&message).unwrap().as_str());
+                    self.rejected_hellos.push(remote_hello);
+                }
+            },
+            Ok(HandshakeMessage::HelloRejected { player_id, rejection }) if player_id == self.local_hello.player_id && self.remote_hellos.is_empty() => {
+                println!("Rejected by peer: {}", rejection);
+                self.state = HandshakeState::Rejected(rejection);
+            }
+            //Addressed to another peer, or sent by a stale peer that the rest of the room agrees with us about.
+            Ok(HandshakeMessage::HelloRejected { .. }) => {}
+            //A peer still counting down in the lobby.
+            Ok(HandshakeMessage::Lobby(_)) => {}
+            Err(e) => println!("Handshake: unreadable message: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::loopbacktransport::{LoopbackRoom, LoopbackTransport};
+
+    fn hello(player_id: u32) -> Hello {
+        Hello::new("tiledmap.json:0000000000000001".to_string(), player_id, format!("Player {}", player_id), player_id as u64 * 1000)
+    }
+
+    #[test]
+    fn compatible_hello_test() {
+        assert_eq!(hello(1).check_compatible(&hello(2)), Ok(()));
+    }
+
+    #[test]
+    fn incompatible_hello_test() {
+        let mut remote = hello(2);
+        remote.protocol_version = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            hello(1).check_compatible(&remote),
+            Err(HandshakeRejection::ProtocolVersionMismatch { local: PROTOCOL_VERSION, remote: PROTOCOL_VERSION + 1 })
+        );
+
+        let mut remote = hello(2);
+        remote.build_hash = "other".to_string();
+        assert!(matches!(hello(1).check_compatible(&remote), Err(HandshakeRejection::BuildMismatch { .. })));
+
+        let mut remote = hello(2);
+        remote.map_id = "other_map".to_string();
+        assert!(matches!(hello(1).check_compatible(&remote), Err(HandshakeRejection::MapMismatch { .. })));
+
+        assert_eq!(hello(1).check_compatible(&hello(1)), Err(HandshakeRejection::DuplicatePlayerId(1)));
+    }
+
+    #[test]
+    fn peers_agree_on_match_seed_and_slots_test() {
+        assert_eq!(hello(1).agree_match_seed(&[hello(2)]), hello(2).agree_match_seed(&[hello(1)]));
+        assert_ne!(hello(1).agree_match_seed(&[hello(2)]), hello(1).agree_match_seed(&[hello(3)]));
+        assert_eq!(hello(1).player_slot(&[hello(2)]), 0);
+        assert_eq!(hello(2).player_slot(&[hello(1)]), 1);
+        assert_eq!(hello(2).player_slot(&[]), 0);
+
+        assert_eq!(hello(3).agree_match_seed(&[hello(1), hello(2)]), hello(1).agree_match_seed(&[hello(3), hello(2)]));
+        assert_eq!(hello(3).player_slot(&[hello(1), hello(2)]), 2);
+        assert_eq!(hello(2).player_slot(&[hello(3), hello(1)]), 1);
+        //Spectators neither contribute to the seed nor take a slot.
+        let mut spectator = hello(0);
+        spectator.role = PeerRole::Spectator;
+        assert_eq!(hello(1).agree_match_seed(&[hello(2), spectator.clone()]), hello(1).agree_match_seed(&[hello(2)]));
+        assert_eq!(hello(1).player_slot(&[hello(2), spectator.clone()]), 0);
+        assert_eq!(spectator.player_slots(&[hello(2), hello(1)]), vec![(1, 0), (2, 1)].into_iter().collect());
+        assert_eq!(hello(2).player_slots(&[hello(1), hello(3)]), hello(3).player_slots(&[hello(2), hello(1)]));
+        assert_eq!(hello(2).player_slots(&[hello(1)]), vec![(1, 0), (2, 1)].into_iter().collect());
+        //Slots picked in the lobby win over the player id order.
+        let mut lobby_hello_1 = hello(1);
+        let mut lobby_hello_2 = hello(2);
+        lobby_hello_1.slot = Some(1);
+        lobby_hello_2.slot = Some(0);
+        assert_eq!(lobby_hello_1.player_slots(&[lobby_hello_2]), vec![(1, 1), (2, 0)].into_iter().collect());
+    }
+
+    #[test]
+    fn handshake_completes_between_peers_test() {
+        let room = LoopbackRoom::default();
+        let (mut transport_a, mut transport_b) = (room.join(), room.join());
+        let mut handshake_a = Handshake::new(hello(1), 2);
+        let mut handshake_b = Handshake::new(hello(2), 2);
+        handshake_b.update(&mut transport_b, 0.0);
+        handshake_a.update(&mut transport_a, 0.0);
+        assert_eq!(handshake_a.state, HandshakeState::Complete(vec![hello(2)]));
+        assert_eq!(handshake_b.state, HandshakeState::Pending);
+
+        //Peer A starts sending game messages before B has finished, they are kept for the GameManager.
+        let frame = wireformat::encode_frame(&[]);
+        transport_a.send_bytes(&frame);
+        handshake_b.update(&mut transport_b, 0.1);
+        assert_eq!(handshake_b.state, HandshakeState::Complete(vec![hello(1)]));
+        assert!(handshake_b.pending_messages.contains(&frame));
+    }
+
+    #[test]
+    fn handshake_waits_for_every_player_test() {
+        let room = LoopbackRoom::default();
+        let mut transports: Vec<LoopbackTransport> = (0..3).map(|_| room.join()).collect();
+        let mut handshakes: Vec<Handshake> = (1..=3).map(|player_id| Handshake::new(hello(player_id), 3)).collect();
+        //Player 3 connects late, after the others have already sent each other their hellos.
+        for i in 0..2 {
+            handshakes[i].update(&mut transports[i], 0.0);
+        }
+        for i in 0..2 {
+            handshakes[i].update(&mut transports[i], 0.1);
+            assert_eq!(handshakes[i].state, HandshakeState::Pending);
+        }
+        let mut time = 0.2;
+        while handshakes.iter().any(|handshake| handshake.state == HandshakeState::Pending) {
+            assert!(time < 2.0);
+            for (handshake, transport) in handshakes.iter_mut().zip(transports.iter_mut()) {
+                handshake.update(transport, time);
+            }
+            time += 0.1;
+        }
+        assert_eq!(handshakes[0].state, HandshakeState::Complete(vec![hello(2), hello(3)]));
+        assert_eq!(handshakes[2].state, HandshakeState::Complete(vec![hello(1), hello(2)]));
+    }
+
+    #[test]
+    fn handshake_completes_with_players_only_test() {
+        let room = LoopbackRoom::default();
+        let (mut transport_a, mut transport_b) = (room.join(), room.join());
+        let mut spectator_hello = hello(3);
+        spectator_hello.role = PeerRole::Spectator;
+        let mut handshake_a = Handshake::new(spectator_hello.clone(), 2);
+        let mut handshake_b = Handshake::new(hello(2), 2);
+        handshake_a.update(&mut transport_a, 0.0);
+        handshake_b.update(&mut transport_b, 0.0);
+        //The player keeps waiting for another player, the spectator's hello is left for the GameManager.
+        assert_eq!(handshake_b.state, HandshakeState::Pending);
+        assert_eq!(handshake_b.pending_messages.len(), 1);
+        handshake_a.update(&mut transport_a, 0.1);
+        assert_eq!(handshake_a.state, HandshakeState::Complete(vec![hello(2)]));
+    }
+
+    #[test]
+    fn server_and_players_wait_for_each_other_test() {
+        let room = LoopbackRoom::default();
+        let mut transports: Vec<LoopbackTransport> = (0..3).map(|_| room.join()).collect();
+        let mut server_hello = hello(9);
+        server_hello.role = PeerRole::Server;
+        let mut handshakes = [Handshake::new(hello(1), 2), Handshake::new(hello(2), 2), Handshake::new(server_hello.clone(), 2)];
+        handshakes[0].expects_server = true;
+        handshakes[1].expects_server = true;
+        //The players have found each other, but do not start without the server.
+        for time in [0.0, 0.1] {
+            for i in 0..2 {
+                handshakes[i].update(&mut transports[i], time);
+                assert_eq!(handshakes[i].state, HandshakeState::Pending);
+            }
+        }
+        let mut time = 0.2;
+        while handshakes.iter().any(|handshake| handshake.state == HandshakeState::Pending) {
+            assert!(time < 2.0);
+            for (handshake, transport) in handshakes.iter_mut().zip(transports.iter_mut()) {
+                handshake.update(transport, time);
+            }
+            time += 0.1;
+        }
+        assert_eq!(handshakes[0].state, HandshakeState::Complete(vec![hello(2), server_hello.clone()]));
+        assert_eq!(handshakes[2].state, HandshakeState::Complete(vec![hello(1), hello(2)]));
+        //The server plays no part in the seed or the slots.
+        assert_eq!(hello(1).agree_match_seed(&[hello(2), server_hello.clone()]), hello(2).agree_match_seed(&[hello(1)]));
+        assert_eq!(hello(2).player_slot(&[hello(1), server_hello]), 1);
+    }
+
+    #[test]
+    fn handshake_rejects_incompatible_peer_test() {
+        let room = LoopbackRoom::default();
+        let (mut transport_a, mut transport_b) = (room.join(), room.join());
+        let mut handshake_a = Handshake::new(hello(1), 2);
+        let mut remote = hello(2);
+        remote.map_id = "other_map".to_string();
+        let mut handshake_b = Handshake::new(remote, 2);
+        handshake_a.update(&mut transport_a, 0.0);
+        handshake_b.update(&mut transport_b, 0.0);
+        handshake_a.update(&mut transport_a, 0.1);
+        handshake_b.update(&mut transport_b, 0.1);
+        //With nobody else around, neither can tell which of them is out of date.
+        assert!(matches!(handshake_a.state, HandshakeState::Rejected(HandshakeRejection::MapMismatch { .. })));
+        assert!(matches!(handshake_b.state, HandshakeState::Rejected(HandshakeRejection::MapMismatch { .. })));
+    }
+
+    #[test]
+    fn stale_peer_does_not_break_the_handshake_test() {
+        let room = LoopbackRoom::default();
+        let mut transports: Vec<LoopbackTransport> = (0..4).map(|_| room.join()).collect();
+        let mut stale = hello(4);
+        stale.build_hash = "old".to_string();
+        //The stale client was in the room first, and rejects every player that arrives after it.
+        let mut handshakes = vec![Handshake::new(stale, 3)];
+        handshakes.extend((1..=3).map(|player_id| Handshake::new(hello(player_id), 3)));
+        let mut time = 0.0;
+        while handshakes.iter().any(|handshake| handshake.state == HandshakeState::Pending) {
+            assert!(time < 2.0);
+            for (handshake, transport) in handshakes.iter_mut().zip(transports.iter_mut()) {
+                handshake.update(transport, time);
+            }
+            time += 0.1;
+        }
+        assert!(matches!(handshakes[0].state, HandshakeState::Rejected(HandshakeRejection::BuildMismatch { .. })));
+        assert_eq!(handshakes[1].state, HandshakeState::Complete(vec![hello(2), hello(3)]));
+        assert_eq!(handshakes[3].state, HandshakeState::Complete(vec![hello(1), hello(2)]));
+    }
+}
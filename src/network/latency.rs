@@ -0,0 +1,132 @@
+use crate::model::gamemanager::TICK_DURATION;
+use crate::model::requests::PlayerID;
+use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
+use std::collections::BTreeMap;
+
+const PING_INTERVAL: f64 = 1.0;
+pub const DEFAULT_INPUT_DELAY: u32 = 10; //Used until the first round trip has been measured.
+pub const MIN_INPUT_DELAY: u32 = 2;
+pub const MAX_INPUT_DELAY: u32 = 30; //Well inside the rollback window, so a late request can still be rolled back to.
+
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Clone, PartialEq)]
+pub struct Ping {
+    pub sender: PlayerID,
+    pub sequence: u32,
+    pub sent_time: f64, //Sender's clock, only ever compared against the sender's clock.
+}
+
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Clone, PartialEq)]
+pub struct Pong {
+    pub ping: Ping,
+    pub responder: PlayerID,
+}
+
+//Smoothed round-trip time as in RFC 6298, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerLatency {
+    pub smoothed_rtt: f64,
+    pub rtt_variance: f64,
+    pub last_rtt: f64,
+}
+
+#[derive(Default)]
+pub struct LatencyTracker {
+    peers: BTreeMap<PlayerID, PeerLatency>,
+    last_ping_time: Option<f64>,
+    next_sequence: u32,
+    now: f64,
+}
+
+impl LatencyTracker {
+    //Advances the clock, returning a ping to broadcast when one is due.
+    pub fn update(&mut self, time: f64, sender: PlayerID) -> Option<Ping> {
+        self.now = time;
+        let ping_due = match self.last_ping_time {
+            Some(last_ping_time) => time - last_ping_time >= PING_INTERVAL,
+            None => true,
+        };
+        if !ping_due {
+            return None;
+        }
+        self.last_ping_time = Some(time);
+        self.next_sequence += 1;
+        Some(Ping { sender, sequence: self.next_sequence, sent_time: time })
+    }
+
+    pub fn add_pong(&mut self, pong: &Pong) {
+        let rtt = self.now - pong.ping.sent_time;
+        if rtt >= 0.0 {
+            self.add_sample(pong.responder, rtt);
+        }
+    }
+
+    pub fn add_sample(&mut self, peer: PlayerID, rtt: f64) {
+        match self.peers.get_mut(&peer) {
+            Some(latency) => {
+                latency.rtt_variance = 0.75 * latency.rtt_variance + 0.25 * (latency.smoothed_rtt - rtt).abs();
+                latency.smoothed_rtt = 0.875 * latency.smoothed_rtt + 0.125 * rtt;
+                latency.last_rtt = rtt;
+            }
+            None => {
+                self.peers.insert(peer, PeerLatency { smoothed_rtt: rtt, rtt_variance: rtt / 2.0, last_rtt: rtt });
+            }
+        }
+    }
+
+    pub fn get_peer_latency(&self, peer: PlayerID) -> Option<PeerLatency> {
+        self.peers.get(&peer).cloned()
+    }
+
+    /*Requests must reach the slowest peer before their tick comes round there, so the delay covers half of
+    that peer's round trip plus a margin for jitter, and one more tick for the frame the request waits in.*/
+    pub fn input_delay_ticks(&self) -> u32 {
+        let worst_one_way = self
+            .peers
+            .values()
+            .map(|latency| (latency.smoothed_rtt + 4.0 * latency.rtt_variance) / 2.0)
+            .fold(None, |worst: Option<f64>, one_way| Some(worst.map_or(one_way, |worst| worst.max(one_way))));
+        match worst_one_way {
+            Some(one_way) => ((one_way / TICK_DURATION).ceil() as u32 + 1).clamp(MIN_INPUT_DELAY, MAX_INPUT_DELAY),
+            None => DEFAULT_INPUT_DELAY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_are_sent_once_per_interval_test() {
+        let mut tracker = LatencyTracker::default();
+        assert_eq!(tracker.update(0.0, 1).unwrap().sequence, 1);
+        assert_eq!(tracker.update(0.5, 1), None);
+        let ping = tracker.update(1.2, 1).unwrap();
+        assert_eq!(ping.sequence, 2);
+        assert_eq!(ping.sent_time, 1.2);
+    }
+
+    #[test]
+    fn input_delay_follows_slowest_peer_test() {
+        let mut tracker = LatencyTracker::default();
+        assert_eq!(tracker.input_delay_ticks(), DEFAULT_INPUT_DELAY);
+
+        let ping = tracker.update(10.0, 1).unwrap();
+        tracker.update(10.1, 1);
+        tracker.add_pong(&Pong { ping, responder: 2 });
+        let latency = tracker.get_peer_latency(2).unwrap();
+        assert!((latency.smoothed_rtt - 0.1).abs() < 1e-9);
+        //(0.1 + 4 * 0.05) / 2 = 0.15s one way, 3 ticks plus one.
+        assert_eq!(tracker.input_delay_ticks(), 4);
+
+        //A LAN peer alone gets the minimum delay, a slow one raises it for everybody.
+        let mut lan_tracker = LatencyTracker::default();
+        for _ in 0..20 {
+            lan_tracker.add_sample(2, 0.002);
+        }
+        assert_eq!(lan_tracker.input_delay_ticks(), MIN_INPUT_DELAY);
+        lan_tracker.add_sample(3, 5.0);
+        assert_eq!(lan_tracker.input_delay_ticks(), MAX_INPUT_DELAY);
+    }
+}
@@ -0,0 +1,372 @@
+use super::handshake::{HandshakeMessage, MAX_PLAYERS};
+use super::transport::{Transport, TransportEvent};
+use super::wireformat;
+use crate::model::gamemanager::TICK_DURATION;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+const LOBBY_RESEND_INTERVAL: f64 = 0.5;
+const MEMBER_TIMEOUT: f64 = 3.0; //A member not heard from for this long has left the room.
+pub const COUNTDOWN_TICKS: u32 = 60; //3 seconds.
+const ROOM_LIST_INTERVAL: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LobbyMember {
+    pub player_id: u32,
+    pub player_name: String,
+    pub slot: u8, //Faction and colour. Also picks the player's unit id block, see IdAllocator.
+    pub map_name: String, //The map this member has picked, only the host's counts.
+    pub ready: bool,
+}
+
+//Sent as JSON before the handshake, inside HandshakeMessage::Lobby.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LobbyMessage {
+    Member(LobbyMember), //Resent every LOBBY_RESEND_INTERVAL, and straight away after a change.
+    Countdown { ticks_left: u32 }, //From the host: the match starts this many ticks from now.
+    CountdownCancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyResult {
+    pub members: Vec<LobbyMember>, //In player id order, including us.
+    pub map_name: String,
+    pub slot: u8,
+    pub start_time: f64, //When the countdown ran out, on the clock passed to Lobby::update.
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyState {
+    Waiting,
+    Countdown(f64), //Seconds left.
+    Starting(LobbyResult),
+}
+
+/*The room before a match. Every member keeps sending its LobbyMember, and the host, the member with the lowest
+player id, picks the map. Once at least `min_players` are in the room and all of them are ready, the host starts a
+countdown of COUNTDOWN_TICKS, which anyone leaving, joining or unreadying cancels. The countdown runs out at the same
+moment for every peer, give or take the relay's latency, so they all start the handshake together.
+Two members picking the same slot is settled in favour of the lower player id, the other one moves to a free slot.*/
+pub struct Lobby {
+    pub local: LobbyMember,
+    pub min_players: usize,
+    pub state: LobbyState,
+    pub pending_messages: Vec<Vec<u8>>, //Anything that is not a lobby message, e.g. the hello of a peer that started first.
+    remotes: BTreeMap<u32, (LobbyMember, f64)>, //With the time they were last heard from.
+    start_time: Option<f64>,
+    last_sent_time: Option<f64>,
+    changed: bool,
+}
+
+impl Lobby {
+    pub fn new(player_id: u32, player_name: String, map_name: String, min_players: usize) -> Self {
+        Lobby {
+            local: LobbyMember { player_id, player_name, slot: 0, map_name, ready: false },
+            min_players,
+            state: LobbyState::Waiting,
+            pending_messages: Vec::new(),
+            remotes: BTreeMap::new(),
+            start_time: None,
+            last_sent_time: None,
+            changed: false,
+        }
+    }
+
+    pub fn members(&self) -> Vec<LobbyMember> {
+        let mut members: Vec<LobbyMember> = self.remotes.values().map(|(member, _)| member.clone()).collect();
+        members.push(self.local.clone());
+        members.sort_by_key(|member| member.player_id);
+        members
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.remotes.keys().all(|player_id| *player_id > self.local.player_id)
+    }
+
+    pub fn map_name(&self) -> String {
+        match self.remotes.values().next() {
+            Some((host, _)) if host.player_id < self.local.player_id => host.map_name.clone(),
+            _ => self.local.map_name.clone(),
+        }
+    }
+
+    fn is_slot_free(&self, slot: u8) -> bool {
+        (slot as usize) < MAX_PLAYERS && self.remotes.values().all(|(member, _)| member.slot != slot)
+    }
+
+    //Fails if the slot is taken, or while we are ready.
+    pub fn choose_slot(&mut self, slot: u8) -> bool {
+        if self.local.ready || !self.is_slot_free(slot) {
+            return false;
+        }
+        self.local.slot = slot;
+        self.changed = true;
+        true
+    }
+
+    //Only the host picks the map.
+    pub fn choose_map(&mut self, map_name: String) -> bool {
+        if self.local.ready || !self.is_host() {
+            return false;
+        }
+        self.local.map_name = map_name;
+        self.changed = true;
+        true
+    }
+
+    pub fn set_ready(&mut self, ready: bool) {
+        self.local.ready = ready;
+        self.changed = true;
+    }
+
+    fn all_ready(&self) -> bool {
+        let members = self.members();
+        let mut slots: Vec<u8> = members.iter().map(|member| member.slot).collect();
+        slots.sort();
+        slots.dedup();
+        members.len() >= self.min_players && members.len() <= MAX_PLAYERS && slots.len() == members.len() && members.iter().all(|member| member.ready)
+    }
+
+    pub fn update(&mut self, transport: &mut dyn Transport, time: f64) -> &LobbyState {
+        if let LobbyState::Starting(_) = self.state {
+            return &self.state;
+        }
+        while let Some(event) = transport.poll_event() {
+            match event {
+                TransportEvent::Message(message) => self.handle_message(message, time),
+                event => println!("Lobby: {:?}", event),
+            }
+        }
+        let before = self.remotes.len();
+        self.remotes.retain(|_, (_, last_heard_time)| time - *last_heard_time < MEMBER_TIMEOUT);
+        if self.remotes.len() != before {
+            println!("A player left the lobby");
+        }
+        let slot_taken = self.remotes.values().any(|(member, _)| member.slot == self.local.slot && member.player_id < self.local.player_id);
+        if slot_taken {
+            //A player with a lower id has our slot, e.g. they picked it at the same time as we did.
+            if let Some(slot) = (0..MAX_PLAYERS as u8).find(|slot| self.is_slot_free(*slot)) {
+                self.local.slot = slot;
+                self.changed = true;
+            }
+        }
+
+        let resend_due = match self.last_sent_time {
+            Some(last_sent_time) => time - last_sent_time >= LOBBY_RESEND_INTERVAL,
+            None => true,
+        };
+        if (resend_due || self.changed) && transport.is_connected() {
+            send(transport, LobbyMessage::Member(self.local.clone()));
+            self.last_sent_time = Some(time);
+            self.changed = false;
+        }
+        if self.is_host() {
+            self.update_countdown(transport, time, resend_due);
+        } else if !self.local.ready {
+            self.start_time = None;
+        }
+
+        self.state = match self.start_time {
+            Some(start_time) if time >= start_time => {
+                println!("Starting match with {} players", self.remotes.len() + 1);
+                LobbyState::Starting(LobbyResult { members: self.members(), map_name: self.map_name(), slot: self.local.slot, start_time })
+            }
+            Some(start_time) => LobbyState::Countdown(start_time - time),
+            None => LobbyState::Waiting,
+        };
+        &self.state
+    }
+
+    //Host only.
+    fn update_countdown(&mut self, transport: &mut dyn Transport, time: f64, resend_due: bool) {
+        match self.start_time {
+            None if self.all_ready() => {
+                println!("Everyone is ready, starting the countdown");
+                self.start_time = Some(time + COUNTDOWN_TICKS as f64 * TICK_DURATION);
+                send(transport, LobbyMessage::Countdown { ticks_left: COUNTDOWN_TICKS });
+            }
+            Some(_) if !self.all_ready() => {
+                println!("Countdown cancelled");
+                self.start_time = None;
+                send(transport, LobbyMessage::CountdownCancelled);
+            }
+            Some(start_time) if resend_due => {
+                let ticks_left = ((start_time - time) / TICK_DURATION).ceil().max(0.0) as u32;
+                send(transport, LobbyMessage::Countdown { ticks_left });
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_message(&mut self, message: Vec<u8>, time: f64) {
+        if wireformat::is_frame(&message) {
+            self.pending_messages.push(message);
+            return;
+        }
+        match serde_json::from_slice(&message) {
+            Ok(HandshakeMessage::Lobby(LobbyMessage::Member(member))) if member.player_id != self.local.player_id => {
+                if !self.remotes.contains_key(&member.player_id) {
+                    println!("{} joined the lobby", member.player_name);
+                }
+                self.remotes.insert(member.player_id, (member, time));
+            }
+            Ok(HandshakeMessage::Lobby(LobbyMessage::Member(_))) => {}
+            Ok(HandshakeMessage::Lobby(LobbyMessage::Countdown { ticks_left })) => {
+                if !self.is_host() && self.local.ready {
+                    self.start_time = Some(time + ticks_left as f64 * TICK_DURATION);
+                }
+            }
+            Ok(HandshakeMessage::Lobby(LobbyMessage::CountdownCancelled)) => self.start_time = None,
+            _ => self.pending_messages.push(message),
+        }
+    }
+}
+
+fn send(transport: &mut dyn Transport, message: LobbyMessage) {
+    transport.send_text(serde_json::to_string(&HandshakeMessage::Lobby(message)).unwrap().as_str());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomInfo {
+    pub room_id: String,
+    pub members: usize,
+    pub playing: bool, //Its match has started.
+}
+
+//Answered by the relay itself instead of being passed on, see relay::room.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RelayMessage {
+    ListRooms,
+    RoomList(Vec<RoomInfo>),
+}
+
+//Keeps the list of rooms on the relay up to date, over a connection that is not in any room.
+#[derive(Default)]
+pub struct RoomBrowser {
+    pub rooms: Vec<RoomInfo>,
+    last_request_time: Option<f64>,
+}
+
+impl RoomBrowser {
+    pub fn update(&mut self, transport: &mut dyn Transport, time: f64) {
+        let request_due = match self.last_request_time {
+            Some(last_request_time) => time - last_request_time >= ROOM_LIST_INTERVAL,
+            None => true,
+        };
+        if request_due && transport.is_connected() {
+            transport.send_text(serde_json::to_string(&RelayMessage::ListRooms).unwrap().as_str());
+            self.last_request_time = Some(time);
+        }
+        while let Some(event) = transport.poll_event() {
+            match event {
+                TransportEvent::Message(message) => {
+                    if let Ok(RelayMessage::RoomList(rooms)) = serde_json::from_slice(&message) {
+                        self.rooms = rooms;
+                    }
+                }
+                event => println!("Room browser: {:?}", event),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::loopbacktransport::{LoopbackRoom, LoopbackTransport};
+
+    fn lobbies(count: usize) -> (Vec<Lobby>, Vec<LoopbackTransport>) {
+        let lobbies = (1..=count as u32).map(|player_id| Lobby::new(player_id, format!("Player {}", player_id), "tiledmap.json".to_string(), 2)).collect();
+        let room = LoopbackRoom::default();
+        (lobbies, (0..count).map(|_| room.join()).collect())
+    }
+
+    fn update_all(lobbies: &mut [Lobby], transports: &mut [LoopbackTransport], time: f64) {
+        for (lobby, transport) in lobbies.iter_mut().zip(transports.iter_mut()) {
+            lobby.update(transport, time);
+        }
+    }
+
+    #[test]
+    fn countdown_starts_once_everyone_is_ready_test() {
+        let (mut lobbies, mut transports) = lobbies(3);
+        update_all(&mut lobbies, &mut transports, 0.0);
+        update_all(&mut lobbies, &mut transports, 0.1);
+        //Everyone started in slot 0, the players with higher ids moved.
+        update_all(&mut lobbies, &mut transports, 0.2);
+        update_all(&mut lobbies, &mut transports, 0.3);
+        let slots: Vec<u8> = lobbies[0].members().iter().map(|member| member.slot).collect();
+        assert_eq!(slots, vec![0, 1, 2]);
+        assert!(lobbies[0].is_host() && !lobbies[1].is_host());
+
+        assert!(!lobbies[1].choose_map("other.json".to_string()));
+        assert!(lobbies[0].choose_map("other.json".to_string()));
+        assert!(!lobbies[1].choose_slot(0));
+        assert!(lobbies[1].choose_slot(5));
+        for lobby in lobbies.iter_mut() {
+            lobby.set_ready(true);
+        }
+        assert!(!lobbies[1].choose_slot(6));
+        update_all(&mut lobbies, &mut transports, 0.4);
+        update_all(&mut lobbies, &mut transports, 0.5);
+        assert!(lobbies.iter().all(|lobby| matches!(lobby.state, LobbyState::Countdown(_))));
+
+        //Player 3 changes their mind, which cancels the countdown for everyone.
+        lobbies[2].set_ready(false);
+        update_all(&mut lobbies, &mut transports, 0.6);
+        update_all(&mut lobbies, &mut transports, 0.7);
+        assert!(lobbies.iter().all(|lobby| lobby.state == LobbyState::Waiting));
+
+        lobbies[2].set_ready(true);
+        let mut time = 0.8;
+        while lobbies.iter().any(|lobby| !matches!(lobby.state, LobbyState::Starting(_))) {
+            assert!(time < 5.0);
+            update_all(&mut lobbies, &mut transports, time);
+            time += 0.1;
+        }
+        let results: Vec<LobbyResult> = lobbies
+            .iter()
+            .map(|lobby| match &lobby.state {
+                LobbyState::Starting(result) => result.clone(),
+                state => panic!("Unexpected {:?}", state),
+            })
+            .collect();
+        for result in &results {
+            assert_eq!(result.map_name, "other.json");
+            assert_eq!(result.members.len(), 3);
+            assert!((result.start_time - results[0].start_time).abs() < 0.15);
+        }
+        assert_eq!(results.iter().map(|result| result.slot).collect::<Vec<u8>>(), vec![0, 5, 2]);
+    }
+
+    #[test]
+    fn members_that_stop_sending_leave_test() {
+        let (mut lobbies, mut transports) = lobbies(2);
+        lobbies[0].min_players = 1;
+        update_all(&mut lobbies, &mut transports, 0.0);
+        update_all(&mut lobbies, &mut transports, 0.1);
+        assert_eq!(lobbies[0].members().len(), 2);
+        lobbies[0].set_ready(true);
+        //Player 2 never readies, then goes away.
+        for time in [1.0, 2.0, 3.0, 4.0] {
+            lobbies[0].update(&mut transports[0], time);
+        }
+        assert_eq!(lobbies[0].members().len(), 1);
+        assert!(matches!(lobbies[0].state, LobbyState::Countdown(_)));
+    }
+
+    #[test]
+    fn room_list_is_requested_from_the_relay_test() {
+        let room = LoopbackRoom::default();
+        let (mut transport, mut relay) = (room.join(), room.join());
+        let mut browser = RoomBrowser::default();
+        browser.update(&mut transport, 0.0);
+        assert_eq!(relay.poll_event(), Some(TransportEvent::Connected));
+        assert_eq!(relay.poll_event(), Some(TransportEvent::Message(br#""ListRooms""#.to_vec())));
+        let rooms = vec![RoomInfo { room_id: "12345".to_string(), members: 2, playing: false }];
+        relay.send_text(&serde_json::to_string(&RelayMessage::RoomList(rooms.clone())).unwrap());
+        browser.update(&mut transport, 1.0);
+        assert_eq!(browser.rooms, rooms);
+        assert_eq!(relay.poll_event(), None);
+    }
+}
@@ -0,0 +1,134 @@
+use super::transport::{Transport, TransportEvent};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct RoomState {
+    inboxes: Vec<VecDeque<Vec<u8>>>, //One per member, in the order they joined.
+    echo: bool, //Members also receive what they send themselves.
+    closed: bool,
+}
+
+/*In-memory stand-in for a relay server room: every member receives what the others send, and disconnecting any
+member closes the room for all of them. Used to run several GameManagers side by side in tests.*/
+#[derive(Default)]
+pub struct LoopbackRoom {
+    state: Rc<RefCell<RoomState>>,
+}
+
+impl LoopbackRoom {
+    pub fn join(&self) -> LoopbackTransport {
+        let mut state = self.state.borrow_mut();
+        state.inboxes.push(VecDeque::new());
+        let mut pending_events = VecDeque::new();
+        pending_events.push_back(TransportEvent::Connected);
+        LoopbackTransport { room: self.state.clone(), member: state.inboxes.len() - 1, connected: true, pending_events }
+    }
+}
+
+/*In-memory transport, a member of a LoopbackRoom. LoopbackTransport::new() is alone in a room that echoes every
+message back to it and is used for offline mode.*/
+pub struct LoopbackTransport {
+    room: Rc<RefCell<RoomState>>,
+    member: usize,
+    connected: bool,
+    pending_events: VecDeque<TransportEvent>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> Self {
+        let room = LoopbackRoom::default();
+        room.state.borrow_mut().echo = true;
+        room.join()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send_bytes(&mut self, data: &[u8]) {
+        let mut room = self.room.borrow_mut();
+        if !self.connected || room.closed {
+            return;
+        }
+        let echo = room.echo;
+        for (member, inbox) in room.inboxes.iter_mut().enumerate() {
+            if member != self.member || echo {
+                inbox.push_back(data.to_vec());
+            }
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(event);
+        }
+        let mut room = self.room.borrow_mut();
+        if let Some(message) = room.inboxes[self.member].pop_front() {
+            return Some(TransportEvent::Message(message));
+        }
+        if self.connected && room.closed {
+            self.connected = false;
+            return Some(TransportEvent::Disconnected);
+        }
+        None
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected && !self.room.borrow().closed
+    }
+
+    fn disconnect(&mut self) {
+        self.room.borrow_mut().closed = true;
+    }
+
+    fn echoes_sent_messages(&self) -> bool {
+        self.room.borrow().echo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn loopback_echoes_in_order_test() {
+        let mut transport = LoopbackTransport::new();
+        assert_eq!(transport.poll_event(), Some(TransportEvent::Connected));
+        assert_eq!(transport.poll_event(), None);
+        transport.send_text("first");
+        transport.send_text("second");
+        assert_eq!(transport.poll_event(), Some(TransportEvent::Message(b"first".to_vec())));
+        assert_eq!(transport.poll_event(), Some(TransportEvent::Message(b"second".to_vec())));
+        assert_eq!(transport.poll_event(), None);
+        assert!(transport.echoes_sent_messages());
+    }
+
+    #[test]
+    fn loopback_pair_test() {
+        let room = LoopbackRoom::default();
+        let (mut a, mut b) = (room.join(), room.join());
+        assert!(!a.echoes_sent_messages());
+        assert_eq!(a.poll_event(), Some(TransportEvent::Connected));
+        assert_eq!(b.poll_event(), Some(TransportEvent::Connected));
+        a.send_text("hello");
+        assert_eq!(a.poll_event(), None);
+        assert_eq!(b.poll_event(), Some(TransportEvent::Message(b"hello".to_vec())));
+        b.disconnect();
+        assert_eq!(a.poll_event(), Some(TransportEvent::Disconnected));
+        assert_eq!(a.poll_event(), None);
+        assert!(!a.is_connected());
+        assert_eq!(b.poll_event(), Some(TransportEvent::Disconnected));
+    }
+
+    #[test]
+    fn loopback_room_delivers_to_every_other_member_test() {
+        let room = LoopbackRoom::default();
+        let mut ends = [room.join(), room.join(), room.join()];
+        for end in ends.iter_mut() {
+            assert_eq!(end.poll_event(), Some(TransportEvent::Connected));
+        }
+        ends[1].send_text("hello");
+        assert_eq!(ends[0].poll_event(), Some(TransportEvent::Message(b"hello".to_vec())));
+        assert_eq!(ends[1].poll_event(), None);
+        assert_eq!(ends[2].poll_event(), Some(TransportEvent::Message(b"hello".to_vec())));
+    }
+}
@@ -0,0 +1,215 @@
+use super::transport::{Clock, Transport, TransportEvent};
+use std::collections::VecDeque;
+
+pub const INITIAL_RECONNECT_DELAY: f64 = 0.5;
+pub const MAX_RECONNECT_DELAY: f64 = 8.0;
+//Peers ping every second, so a connection this quiet after traffic has flowed is treated as dropped.
+pub const CONNECTION_TIMEOUT: f64 = 5.0;
+
+pub type Connector = Box<dyn FnMut() -> Result<Box<dyn Transport>, String>>;
+
+/*Wraps another transport and opens a new one with `connect` whenever it drops, waiting INITIAL_RECONNECT_DELAY
+before the first retry and doubling the wait after every failed attempt up to MAX_RECONNECT_DELAY. Messages sent
+while disconnected are dropped, GameManager asks its peers for whatever they missed once it is connected again.*/
+pub struct ReconnectingTransport {
+    connect: Connector,
+    clock: Clock,
+    inner: Option<Box<dyn Transport>>,
+    reconnect_delay: f64,
+    reconnect_at: f64,
+    last_message_time: Option<f64>, //Only set once the current connection has delivered something.
+    pending_events: VecDeque<TransportEvent>,
+}
+
+impl ReconnectingTransport {
+    pub fn new(connect: Connector, clock: Clock) -> Self {
+        let now = clock();
+        let mut transport = ReconnectingTransport {
+            connect,
+            clock,
+            inner: None,
+            reconnect_delay: INITIAL_RECONNECT_DELAY,
+            reconnect_at: now,
+            last_message_time: None,
+            pending_events: VecDeque::new(),
+        };
+        transport.try_connect(now);
+        transport
+    }
+
+    fn try_connect(&mut self, now: f64) {
+        match (self.connect)() {
+            Ok(inner) => {
+                self.inner = Some(inner);
+                self.last_message_time = None;
+            }
+            Err(e) => {
+                self.pending_events.push_back(TransportEvent::Error(e));
+                self.schedule_reconnect(now);
+            }
+        }
+    }
+
+    fn schedule_reconnect(&mut self, now: f64) {
+        println!("Reconnecting in {:.1}s", self.reconnect_delay);
+        self.reconnect_at = now + self.reconnect_delay;
+        self.reconnect_delay = (self.reconnect_delay * 2.0).min(MAX_RECONNECT_DELAY);
+    }
+
+    fn drop_connection(&mut self, now: f64) {
+        if let Some(mut inner) = self.inner.take() {
+            inner.disconnect();
+            self.pending_events.push_back(TransportEvent::Disconnected);
+            self.schedule_reconnect(now);
+        }
+    }
+}
+
+impl Transport for ReconnectingTransport {
+    fn send_bytes(&mut self, data: &[u8]) {
+        if let Some(inner) = &mut self.inner {
+            inner.send_bytes(data);
+        }
+    }
+
+    fn send_text(&mut self, text: &str) {
+        if let Some(inner) = &mut self.inner {
+            inner.send_text(text);
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(event);
+        }
+        let now = (self.clock)();
+        if self.inner.is_none() && now >= self.reconnect_at {
+            self.try_connect(now);
+        }
+        let event = match &mut self.inner {
+            Some(inner) => inner.poll_event(),
+            None => return self.pending_events.pop_front(),
+        };
+        match event {
+            Some(TransportEvent::Connected) => {
+                self.reconnect_delay = INITIAL_RECONNECT_DELAY;
+                Some(TransportEvent::Connected)
+            }
+            Some(TransportEvent::Disconnected) => {
+                self.drop_connection(now);
+                self.pending_events.pop_front()
+            }
+            Some(TransportEvent::Message(message)) => {
+                self.last_message_time = Some(now);
+                Some(TransportEvent::Message(message))
+            }
+            Some(event) => Some(event),
+            None => {
+                if self.last_message_time.is_some_and(|last_message_time| now - last_message_time > CONNECTION_TIMEOUT) {
+                    self.pending_events.push_back(TransportEvent::Error("Connection timed out".to_string()));
+                    self.drop_connection(now);
+                }
+                self.pending_events.pop_front()
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.as_ref().is_some_and(|inner| inner.is_connected())
+    }
+
+    //Disconnecting on purpose does not reconnect.
+    fn disconnect(&mut self) {
+        if let Some(mut inner) = self.inner.take() {
+            inner.disconnect();
+        }
+        self.reconnect_at = f64::INFINITY;
+    }
+
+    fn echoes_sent_messages(&self) -> bool {
+        self.inner.as_ref().is_some_and(|inner| inner.echoes_sent_messages())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::loopbacktransport::{LoopbackRoom, LoopbackTransport};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    //Connects to a fresh loopback pair once `failures` attempts have failed, handing the far end to `remote`.
+    fn connector(failures: Rc<Cell<u32>>, remote: Rc<RefCell<Option<LoopbackTransport>>>) -> Connector {
+        Box::new(move || {
+            if failures.get() > 0 {
+                failures.set(failures.get() - 1);
+                return Err("Connection refused".to_string());
+            }
+            let room = LoopbackRoom::default();
+            let (local, far_end) = (room.join(), room.join());
+            *remote.borrow_mut() = Some(far_end);
+            Ok(Box::new(local) as Box<dyn Transport>)
+        })
+    }
+
+    fn drain(transport: &mut ReconnectingTransport) -> Vec<TransportEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = transport.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn reconnects_with_backoff_test() {
+        let time = Rc::new(Cell::new(0.0));
+        let failures = Rc::new(Cell::new(0));
+        let remote = Rc::new(RefCell::new(None));
+        let clock_time = time.clone();
+        let mut transport = ReconnectingTransport::new(connector(failures.clone(), remote.clone()), Box::new(move || clock_time.get()));
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Connected]);
+        assert!(transport.is_connected());
+
+        //The far end drops and the first two attempts to reconnect fail.
+        failures.set(2);
+        remote.borrow_mut().take().unwrap().disconnect();
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Disconnected]);
+        assert!(!transport.is_connected());
+        transport.send_text("lost");
+        let mut attempts = Vec::new();
+        while !transport.is_connected() {
+            time.set(time.get() + 0.25);
+            for event in drain(&mut transport) {
+                match event {
+                    TransportEvent::Error(_) => attempts.push(time.get()),
+                    TransportEvent::Connected => attempts.push(time.get()),
+                    event => panic!("Unexpected {:?}", event),
+                }
+            }
+        }
+        //Retries after 0.5s, then 1s, then 2s.
+        assert_eq!(attempts, vec![0.5, 1.5, 3.5]);
+
+        let mut far_end = remote.borrow_mut().take().unwrap();
+        transport.send_text("after reconnect");
+        assert_eq!(far_end.poll_event(), Some(TransportEvent::Connected));
+        assert_eq!(far_end.poll_event(), Some(TransportEvent::Message(b"after reconnect".to_vec())));
+    }
+
+    #[test]
+    fn silent_connection_times_out_test() {
+        let time = Rc::new(Cell::new(0.0));
+        let remote = Rc::new(RefCell::new(None));
+        let clock_time = time.clone();
+        let mut transport = ReconnectingTransport::new(connector(Rc::new(Cell::new(0)), remote.clone()), Box::new(move || clock_time.get()));
+        //Quiet before anything has been received, e.g. waiting alone in a room, is not a timeout.
+        time.set(20.0);
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Connected]);
+        remote.borrow_mut().as_mut().unwrap().send_text("ping");
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Message(b"ping".to_vec())]);
+        time.set(20.0 + CONNECTION_TIMEOUT + 0.1);
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Error("Connection timed out".to_string()), TransportEvent::Disconnected]);
+        time.set(30.0);
+        assert_eq!(drain(&mut transport), vec![TransportEvent::Connected]);
+    }
+}
@@ -0,0 +1,202 @@
+use super::transport::{Clock, Transport, TransportEvent};
+use crate::model::random::SimulationRng;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+//How long a reordered message is held back, so that the messages sent after it overtake it.
+const REORDER_HOLD: f64 = 0.1;
+
+//Applied to each direction separately, so the round trip is twice the latency.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkConditions {
+    pub latency: f64, //Seconds.
+    pub jitter: f64, //Seconds, each message is delayed by a random extra amount up to this.
+    pub reorder_chance: f64, //0 to 1.
+    pub loss_chance: f64, //0 to 1.
+}
+
+impl NetworkConditions {
+    pub fn is_perfect(&self) -> bool {
+        *self == NetworkConditions::default()
+    }
+}
+
+struct DelayedMessage {
+    deliver_at: f64,
+    data: Vec<u8>,
+}
+
+/*Wraps another transport and makes it behave like a bad network, for testing rollback and resync without real
+infrastructure. Messages are delayed, reordered and dropped in both directions according to the shared
+NetworkConditions, which can be changed while the game is running. Connection events are passed on untouched.*/
+pub struct SimulatedTransport {
+    inner: Box<dyn Transport>,
+    clock: Clock,
+    conditions: Rc<Cell<NetworkConditions>>,
+    rng: SimulationRng,
+    outgoing: VecDeque<DelayedMessage>, //Kept sorted by deliver_at.
+    incoming: VecDeque<DelayedMessage>,
+}
+
+impl SimulatedTransport {
+    pub fn new(inner: Box<dyn Transport>, clock: Clock, seed: u64) -> Self {
+        SimulatedTransport {
+            inner,
+            clock,
+            conditions: Rc::new(Cell::new(NetworkConditions::default())),
+            rng: SimulationRng::new(seed),
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+        }
+    }
+
+    //Shared handle, changes take effect for messages sent or received from then on.
+    pub fn conditions(&self) -> Rc<Cell<NetworkConditions>> {
+        self.conditions.clone()
+    }
+
+    //Returns when the message should be delivered, or None if it is lost.
+    fn delivery_time(&mut self, now: f64) -> Option<f64> {
+        let conditions = self.conditions.get();
+        if conditions.is_perfect() {
+            return Some(now);
+        }
+        if self.rng.next_f64() < conditions.loss_chance {
+            return None;
+        }
+        let mut delay = conditions.latency + conditions.jitter * self.rng.next_f64();
+        if self.rng.next_f64() < conditions.reorder_chance {
+            delay += REORDER_HOLD;
+        }
+        Some(now + delay)
+    }
+
+    fn schedule(queue: &mut VecDeque<DelayedMessage>, message: DelayedMessage) {
+        let index = queue.partition_point(|queued| queued.deliver_at <= message.deliver_at);
+        queue.insert(index, message);
+    }
+
+    fn take_due(queue: &mut VecDeque<DelayedMessage>, now: f64) -> Option<Vec<u8>> {
+        match queue.front() {
+            Some(message) if message.deliver_at <= now => queue.pop_front().map(|message| message.data),
+            _ => None,
+        }
+    }
+}
+
+impl Transport for SimulatedTransport {
+    fn send_bytes(&mut self, data: &[u8]) {
+        let now = (self.clock)();
+        if let Some(deliver_at) = self.delivery_time(now) {
+            SimulatedTransport::schedule(&mut self.outgoing, DelayedMessage { deliver_at, data: data.to_vec() });
+        }
+        while let Some(data) = SimulatedTransport::take_due(&mut self.outgoing, now) {
+            self.inner.send_bytes(&data);
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        let now = (self.clock)();
+        while let Some(data) = SimulatedTransport::take_due(&mut self.outgoing, now) {
+            self.inner.send_bytes(&data);
+        }
+        while let Some(event) = self.inner.poll_event() {
+            match event {
+                TransportEvent::Message(data) => {
+                    if let Some(deliver_at) = self.delivery_time(now) {
+                        SimulatedTransport::schedule(&mut self.incoming, DelayedMessage { deliver_at, data });
+                    }
+                }
+                event => return Some(event),
+            }
+        }
+        SimulatedTransport::take_due(&mut self.incoming, now).map(TransportEvent::Message)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn disconnect(&mut self) {
+        self.outgoing.clear();
+        self.incoming.clear();
+        self.inner.disconnect();
+    }
+
+    fn echoes_sent_messages(&self) -> bool {
+        self.inner.echoes_sent_messages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::loopbacktransport::{LoopbackRoom, LoopbackTransport};
+
+    fn simulated_pair(time: &Rc<Cell<f64>>, conditions: NetworkConditions) -> (SimulatedTransport, LoopbackTransport) {
+        let room = LoopbackRoom::default();
+        let (local, mut remote) = (room.join(), room.join());
+        let clock_time = time.clone();
+        let mut transport = SimulatedTransport::new(Box::new(local), Box::new(move || clock_time.get()), 7);
+        transport.conditions().set(conditions);
+        assert_eq!(transport.poll_event(), Some(TransportEvent::Connected));
+        assert_eq!(remote.poll_event(), Some(TransportEvent::Connected));
+        (transport, remote)
+    }
+
+    fn drain_messages(transport: &mut dyn Transport) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        while let Some(event) = transport.poll_event() {
+            if let TransportEvent::Message(message) = event {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn perfect_network_passes_messages_straight_through_test() {
+        let time = Rc::new(Cell::new(0.0));
+        let (mut transport, mut remote) = simulated_pair(&time, NetworkConditions::default());
+        transport.send_text("now");
+        assert_eq!(drain_messages(&mut remote), vec![b"now".to_vec()]);
+        remote.send_text("back");
+        assert_eq!(drain_messages(&mut transport), vec![b"back".to_vec()]);
+    }
+
+    #[test]
+    fn latency_is_applied_in_both_directions_test() {
+        let time = Rc::new(Cell::new(0.0));
+        let (mut transport, mut remote) = simulated_pair(&time, NetworkConditions { latency: 0.1, ..NetworkConditions::default() });
+        transport.send_text("out");
+        remote.send_text("in");
+        time.set(0.05);
+        assert!(drain_messages(&mut transport).is_empty());
+        assert!(drain_messages(&mut remote).is_empty());
+        //The outgoing message is released by polling, the incoming one waits from when it was received.
+        time.set(0.1);
+        assert!(drain_messages(&mut transport).is_empty());
+        assert_eq!(drain_messages(&mut remote), vec![b"out".to_vec()]);
+        time.set(0.2);
+        assert_eq!(drain_messages(&mut transport), vec![b"in".to_vec()]);
+    }
+
+    #[test]
+    fn messages_are_reordered_and_lost_test() {
+        let time = Rc::new(Cell::new(0.0));
+        let (mut transport, mut remote) = simulated_pair(&time, NetworkConditions { latency: 0.05, jitter: 0.02, reorder_chance: 0.2, loss_chance: 0.1 });
+        for i in 0..1000u32 {
+            time.set(i as f64 * 0.01);
+            transport.send_bytes(&i.to_le_bytes());
+            transport.poll_event();
+        }
+        time.set(100.0);
+        transport.poll_event();
+        let received: Vec<u32> = drain_messages(&mut remote).iter().map(|message| u32::from_le_bytes([message[0], message[1], message[2], message[3]])).collect();
+        let lost = 1000 - received.len();
+        assert!(lost > 50 && lost < 150, "lost {}", lost);
+        let overtaken = received.windows(2).filter(|pair| pair[0] > pair[1]).count();
+        assert!(overtaken > 50, "overtaken {}", overtaken);
+    }
+}
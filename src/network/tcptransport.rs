@@ -0,0 +1,183 @@
+use super::transport::{Transport, TransportEvent};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+
+/*Plain TCP transport for native builds. Each message is framed with a 4 byte big-endian length prefix.*/
+//Same limit as the relay server. A longer length prefix closes the connection instead of being buffered for.
+const MAX_MESSAGE_LENGTH: usize = 16 << 20;
+
+pub struct TcpTransport {
+    stream: Option<TcpStream>,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    pending_events: VecDeque<TransportEvent>,
+}
+
+impl TcpTransport {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, String> {
+        match TcpStream::connect(addr) {
+            Ok(stream) => TcpTransport::from_stream(stream),
+            Err(e) => Err(format!("Failed to connect: {}", e)),
+        }
+    }
+
+    //Wraps an already established stream, e.g. one accepted from a TcpListener.
+    pub fn from_stream(stream: TcpStream) -> Result<Self, String> {
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut pending_events = VecDeque::new();
+        pending_events.push_back(TransportEvent::Connected);
+        Ok(TcpTransport { stream: Some(stream), read_buffer: Vec::new(), write_buffer: Vec::new(), pending_events })
+    }
+
+    fn close_with(&mut self, error: Option<String>) {
+        self.read_buffer.clear();
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+            if let Some(error) = error {
+                self.pending_events.push_back(TransportEvent::Error(error));
+            }
+            self.pending_events.push_back(TransportEvent::Disconnected);
+        }
+    }
+
+    fn flush_writes(&mut self) {
+        while !self.write_buffer.is_empty() {
+            let result = match &mut self.stream {
+                Some(stream) => stream.write(&self.write_buffer),
+                None => return,
+            };
+            match result {
+                Ok(0) => return self.close_with(None),
+                Ok(written) => {
+                    self.write_buffer.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return self.close_with(Some(e.to_string())),
+            }
+        }
+    }
+
+    fn fill_read_buffer(&mut self) {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let result = match &mut self.stream {
+                Some(stream) => stream.read(&mut chunk),
+                None => return,
+            };
+            match result {
+                Ok(0) => return self.close_with(None),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return self.close_with(Some(e.to_string())),
+            }
+        }
+    }
+
+    fn take_message(&mut self) -> Option<Vec<u8>> {
+        if self.read_buffer.len() < 4 {
+            return None;
+        }
+        let length = u32::from_be_bytes([self.read_buffer[0], self.read_buffer[1], self.read_buffer[2], self.read_buffer[3]]) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            self.close_with(Some(format!("Message of {} bytes is over the {} byte limit", length, MAX_MESSAGE_LENGTH)));
+            return None;
+        }
+        if self.read_buffer.len() < 4 + length {
+            return None;
+        }
+        let message = self.read_buffer[4..4 + length].to_vec();
+        self.read_buffer.drain(..4 + length);
+        Some(message)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_bytes(&mut self, data: &[u8]) {
+        if self.stream.is_none() {
+            return;
+        }
+        self.write_buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.write_buffer.extend_from_slice(data);
+        self.flush_writes();
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(event);
+        }
+        self.flush_writes();
+        if let Some(message) = self.take_message() {
+            return Some(TransportEvent::Message(message));
+        }
+        self.fill_read_buffer();
+        if let Some(message) = self.take_message() {
+            return Some(TransportEvent::Message(message));
+        }
+        self.pending_events.pop_front()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn disconnect(&mut self) {
+        self.flush_writes();
+        self.close_with(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    fn poll_until_message(transport: &mut TcpTransport) -> Option<TransportEvent> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            match transport.poll_event() {
+                Some(TransportEvent::Connected) | None => std::thread::sleep(Duration::from_millis(1)),
+                event => return event,
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn tcp_transport_round_trip_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::from_stream(listener.accept().unwrap().0).unwrap();
+
+        client.send_text("first");
+        client.send_text("second");
+        assert_eq!(poll_until_message(&mut server), Some(TransportEvent::Message(b"first".to_vec())));
+        assert_eq!(poll_until_message(&mut server), Some(TransportEvent::Message(b"second".to_vec())));
+
+        server.send_text("reply");
+        assert_eq!(poll_until_message(&mut client), Some(TransportEvent::Message(b"reply".to_vec())));
+        server.send_bytes(&[0xEB, 0, 1]);
+        assert_eq!(poll_until_message(&mut client), Some(TransportEvent::Message(vec![0xEB, 0, 1])));
+
+        client.disconnect();
+        assert!(!client.is_connected());
+        assert_eq!(poll_until_message(&mut server), Some(TransportEvent::Disconnected));
+        assert!(!server.is_connected());
+    }
+
+    #[test]
+    fn oversized_length_prefix_closes_the_connection_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::from_stream(listener.accept().unwrap().0).unwrap();
+        client.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        client.write_all(b"not nearly 4 GiB").unwrap();
+        assert!(matches!(poll_until_message(&mut server), Some(TransportEvent::Error(_))));
+        assert_eq!(server.poll_event(), Some(TransportEvent::Disconnected));
+        assert!(!server.is_connected());
+    }
+}
@@ -0,0 +1,28 @@
+/*Transport abstraction used by GameManager. Implementations deliver whole messages and report connection
+state changes alongside the data so the lockstep logic does not need to know what is underneath.*/
+
+//Seconds, e.g. macroquad's get_time. Transports that need the time take one so tests can drive it by hand.
+pub type Clock = Box<dyn Fn() -> f64>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    Connected,
+    Disconnected,
+    Error(String),
+    Message(Vec<u8>),
+}
+
+pub trait Transport {
+    fn send_bytes(&mut self, data: &[u8]);
+    fn send_text(&mut self, text: &str) {
+        self.send_bytes(text.as_bytes());
+    }
+    //Returns the next pending event, or None when there is nothing to process this frame.
+    fn poll_event(&mut self) -> Option<TransportEvent>;
+    fn is_connected(&self) -> bool;
+    fn disconnect(&mut self);
+    //True if messages we send are delivered back to us (offline loopback), in which case they must not also be queued locally.
+    fn echoes_sent_messages(&self) -> bool {
+        false
+    }
+}
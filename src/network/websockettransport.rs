@@ -0,0 +1,59 @@
+use super::transport::{Transport, TransportEvent};
+use quad_net::web_socket::WebSocket;
+
+/*Transport over the relay server's WebSocket. quad_net only exposes a connected flag, so connect and
+disconnect events are produced by watching that flag change between polls.*/
+pub struct WebSocketTransport {
+    socket: WebSocket,
+    was_connected: bool,
+    disconnected: bool,
+}
+
+impl WebSocketTransport {
+    pub fn connect(url: &str) -> Result<Self, String> {
+        match WebSocket::connect(url) {
+            Ok(socket) => Ok(WebSocketTransport { socket, was_connected: false, disconnected: false }),
+            Err(e) => Err(format!("Failed to connect to {}: {:?}", url, e)),
+        }
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send_bytes(&mut self, data: &[u8]) {
+        if !self.disconnected {
+            self.socket.send_bytes(data);
+        }
+    }
+
+    fn send_text(&mut self, text: &str) {
+        if !self.disconnected {
+            self.socket.send_text(text);
+        }
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        if self.disconnected {
+            return None;
+        }
+        let connected = self.socket.connected();
+        if connected && !self.was_connected {
+            self.was_connected = true;
+            return Some(TransportEvent::Connected);
+        }
+        if !connected && self.was_connected {
+            self.was_connected = false;
+            return Some(TransportEvent::Disconnected);
+        }
+        self.socket.try_recv().map(TransportEvent::Message)
+    }
+
+    fn is_connected(&self) -> bool {
+        !self.disconnected && self.socket.connected()
+    }
+
+    //quad_net has no close call, so the socket is just abandoned.
+    fn disconnect(&mut self) {
+        self.disconnected = true;
+        self.was_connected = false;
+    }
+}
@@ -0,0 +1,220 @@
+use crate::model::messages::NetworkMessage;
+use nanoserde::{DeBin, DeBinErr, SerBin};
+
+/*Binary framing for game traffic. A frame batches every message queued during one frame:
+
+    [FRAME_MAGIC: u8][WIRE_FORMAT_VERSION: u8][message count: u16 LE]
+    then per message: [payload length: u32 LE][nanoserde binary NetworkMessage]
+
+FRAME_MAGIC can never start a JSON document, so frames can be told apart from the JSON handshake messages
+that share the same transport.*/
+pub const FRAME_MAGIC: u8 = 0xEB;
+//Bump whenever the binary layout of NetworkMessage changes.
+pub const WIRE_FORMAT_VERSION: u8 = 9;
+
+const HEADER_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WireFormatError {
+    NotAFrame,
+    UnsupportedVersion(u8),
+    Truncated,
+    Malformed(String),
+}
+
+impl std::fmt::Display for WireFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WireFormatError::NotAFrame => write!(f, "Not a binary frame"),
+            WireFormatError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported wire format version {} (expected {})", version, WIRE_FORMAT_VERSION)
+            }
+            WireFormatError::Truncated => write!(f, "Frame is truncated"),
+            WireFormatError::Malformed(e) => write!(f, "Malformed message: {}", e),
+        }
+    }
+}
+
+pub fn is_frame(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&FRAME_MAGIC)
+}
+
+//Frames hold at most u16::MAX messages, callers with more than that should split them.
+pub fn encode_frame(messages: &[NetworkMessage]) -> Vec<u8> {
+    assert!(messages.len() <= u16::MAX as usize);
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + messages.len() * 32);
+    frame.push(FRAME_MAGIC);
+    frame.push(WIRE_FORMAT_VERSION);
+    frame.extend_from_slice(&(messages.len() as u16).to_le_bytes());
+    for message in messages {
+        let payload = message.serialize_bin();
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+    }
+    frame
+}
+
+/*nanoserde's DeBin for Vec<T> reserves whatever length the data claims before reading a single element, so a
+hostile length could abort the process. Every element takes at least one byte, so a length running past the end of
+the data is rejected up front, and the Vec only grows as elements are actually read. Types holding a Vec implement
+DeBin by hand with this instead of deriving it.*/
+pub fn de_bin_vec<T: DeBin>(o: &mut usize, d: &[u8]) -> Result<Vec<T>, DeBinErr> {
+    let length: usize = DeBin::de_bin(o, d)?;
+    if length > d.len() - *o {
+        //Fail the same way nanoserde fails any other read past the end of the data.
+        let mut end = d.len();
+        return Err(u8::de_bin(&mut end, d).unwrap_err());
+    }
+    let mut out = Vec::new();
+    for _ in 0..length {
+        out.push(DeBin::de_bin(o, d)?);
+    }
+    Ok(out)
+}
+
+pub fn decode_frame(bytes: &[u8]) -> Result<Vec<NetworkMessage>, WireFormatError> {
+    if !is_frame(bytes) {
+        return Err(WireFormatError::NotAFrame);
+    }
+    if bytes.len() < HEADER_LENGTH {
+        return Err(WireFormatError::Truncated);
+    }
+    if bytes[1] != WIRE_FORMAT_VERSION {
+        return Err(WireFormatError::UnsupportedVersion(bytes[1]));
+    }
+    let count = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+    //Every message has at least its length prefix, so a frame cannot hold more than that.
+    let mut messages = Vec::with_capacity(count.min((bytes.len() - HEADER_LENGTH) / 4));
+    let mut offset = HEADER_LENGTH;
+    for _ in 0..count {
+        if bytes.len() - offset < 4 {
+            return Err(WireFormatError::Truncated);
+        }
+        let length = u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as usize;
+        offset += 4;
+        //offset + length can overflow on 32 bit targets such as wasm.
+        let end = match offset.checked_add(length) {
+            Some(end) if end <= bytes.len() => end,
+            _ => return Err(WireFormatError::Truncated),
+        };
+        match NetworkMessage::deserialize_bin(&bytes[offset..end]) {
+            Ok(message) => messages.push(message),
+            Err(e) => return Err(WireFormatError::Malformed(format!("{:?}", e))),
+        }
+        offset = end;
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::checksum::StateChecksum;
+    use crate::model::gamestate::SimulationState;
+    use crate::model::messages::{ReceivedRequests, RetransmitRequest};
+    use crate::model::random::SimulationRng;
+    use crate::model::requests::{Request, RequestID, SpriteCreateRequest, SpriteMoveRequest, SpriteType};
+    use crate::model::snapshot::GameStateSnapshot;
+    use crate::pathfinding::pathfinder::TilePosition;
+    use crate::sprites::engineersprite::Engineer;
+    use crate::sprites::mechsprite::Mech;
+    use crate::sprites::sprite::SpriteID;
+
+    fn requests() -> Vec<NetworkMessage> {
+        vec![
+            NetworkMessage::Request(Request::SpriteCreate(SpriteCreateRequest {tick: 12, player_id: 7, sequence: 1, sprite_uuid: 3000000000, position: TilePosition{x: 4, y: 7}, sprite_type: SpriteType::Mech})),
+            NetworkMessage::Request(Request::SpriteMove(SpriteMoveRequest {tick: 20, player_id: 7, sequence: 2, sprite_uuid: 3000000000, position: TilePosition{x: 9, y: 2}})),
+            NetworkMessage::ResyncRequest,
+        ]
+    }
+
+    //One of every message holding a list, whose DeBin impls are written by hand.
+    fn messages_with_lists() -> Vec<NetworkMessage> {
+        let mut engineer = Engineer::new(1, 1, 64, 64, 1 << 24, 7);
+        engineer.current_path = vec![TilePosition{x: 2, y: 1}, TilePosition{x: 3, y: 2}];
+        let state = SimulationState { units: vec![SpriteID::Engineer(engineer), SpriteID::Mech(Mech::new(5, 5, 64, 64, 2 << 24, 8))], rng: SimulationRng::new(42) };
+        let received_sequences = vec![RequestID { player_id: 7, sequence: 2 }, RequestID { player_id: 8, sequence: 0 }];
+        let request = Request::SpriteMove(SpriteMoveRequest {tick: 41, player_id: 7, sequence: 2, sprite_uuid: 1 << 24, position: TilePosition{x: 9, y: 2}});
+        vec![
+            NetworkMessage::Checksum(StateChecksum { tick: 40, checksum: 99, unit_checksums: vec![(1 << 24, 5), (2 << 24, 6)] }),
+            NetworkMessage::Snapshot(GameStateSnapshot { tick: 40, state, requests: vec![request], received_sequences: received_sequences.clone() }),
+            NetworkMessage::RetransmitRequest(RetransmitRequest { player_id: 7, sequences: vec![3, 4, 5] }),
+            NetworkMessage::ReceivedRequests(ReceivedRequests { sender: 8, received: received_sequences, is_reply: true }),
+        ]
+    }
+
+    #[test]
+    fn frame_round_trip_test() {
+        let frame = encode_frame(&requests());
+        assert!(is_frame(&frame));
+        let decoded = decode_frame(&frame).unwrap();
+        assert_eq!(decoded.len(), 3);
+        for (decoded, original) in decoded.iter().zip(requests().iter()) {
+            assert_eq!(decoded.serialize_bin(), original.serialize_bin());
+        }
+        assert_eq!(decode_frame(&encode_frame(&[])).unwrap().len(), 0);
+
+        let decoded = decode_frame(&encode_frame(&messages_with_lists())).unwrap();
+        assert_eq!(decoded.len(), 4);
+        for (decoded, original) in decoded.iter().zip(messages_with_lists().iter()) {
+            assert_eq!(decoded.serialize_bin(), original.serialize_bin());
+        }
+    }
+
+    #[test]
+    fn hostile_lengths_are_rejected_test() {
+        let frame = encode_frame(&[NetworkMessage::RetransmitRequest(RetransmitRequest { player_id: 7, sequences: vec![3, 4] })]);
+        //The list length is the only 2 encoded as a u64.
+        let length_offset = frame.windows(8).position(|window| window == 2u64.to_le_bytes()).unwrap();
+        for length in [3, 1 << 40, u64::MAX] {
+            let mut hostile = frame.clone();
+            hostile[length_offset..length_offset + 8].copy_from_slice(&length.to_le_bytes());
+            assert!(matches!(decode_frame(&hostile), Err(WireFormatError::Malformed(_))));
+        }
+
+        //A payload length that runs past the end of the frame, or would wrap around on 32 bit targets.
+        for length in [frame.len() as u32, u32::MAX] {
+            let mut hostile = frame.clone();
+            hostile[HEADER_LENGTH..HEADER_LENGTH + 4].copy_from_slice(&length.to_le_bytes());
+            assert_eq!(decode_frame(&hostile).err(), Some(WireFormatError::Truncated));
+        }
+        let mut hostile = frame.clone();
+        hostile[2..4].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(decode_frame(&hostile).err(), Some(WireFormatError::Truncated));
+    }
+
+    //Corrupts valid frames at random. Decoding may fail, but must never panic or allocate without bound.
+    #[test]
+    fn corrupted_frames_do_not_panic_test() {
+        let frame = encode_frame(&messages_with_lists());
+        let mut rng = SimulationRng::new(7);
+        for _ in 0..20000 {
+            let mut corrupted = frame.clone();
+            for _ in 0..1 + rng.next_u32() % 4 {
+                let index = 1 + rng.next_u32() as usize % (corrupted.len() - 1);
+                corrupted[index] = rng.next_u32() as u8;
+            }
+            if rng.next_u32().is_multiple_of(4) {
+                corrupted.truncate(1 + rng.next_u32() as usize % (frame.len() - 1));
+            }
+            let _ = decode_frame(&corrupted);
+        }
+    }
+
+    #[test]
+    fn frame_is_smaller_than_json_test() {
+        let json_length: usize = requests().iter().map(|message| serde_json::to_string(message).unwrap().len()).sum();
+        assert!(encode_frame(&requests()).len() < json_length);
+    }
+
+    #[test]
+    fn invalid_frames_are_rejected_test() {
+        let frame = encode_frame(&requests());
+        assert_eq!(decode_frame(b"{\"ResyncRequest\"}").err(), Some(WireFormatError::NotAFrame));
+        assert_eq!(decode_frame(&frame[..frame.len() - 1]).err(), Some(WireFormatError::Truncated));
+        assert_eq!(decode_frame(&frame[..2]).err(), Some(WireFormatError::Truncated));
+        let mut future_frame = frame.clone();
+        future_frame[1] = WIRE_FORMAT_VERSION + 1;
+        assert_eq!(decode_frame(&future_frame).err(), Some(WireFormatError::UnsupportedVersion(WIRE_FORMAT_VERSION + 1)));
+    }
+}
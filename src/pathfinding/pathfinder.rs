@@ -1,10 +1,10 @@
 use super::super::tiledmap;
 use macroquad::prelude::*;
 use std::collections::HashMap;
-use std::collections::VecDeque;
 use std::collections::BinaryHeap;
 use std::cmp::Ordering;
 use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
 
 // Pathfinder struct that contains a tilemap
 pub struct Pathfinder {
@@ -12,7 +12,7 @@ pub struct Pathfinder {
 }
 
 // TilePosition struct that represents the position of a tile on the map
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[derive(Serialize, Deserialize, SerBin, DeBin, Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
 pub struct TilePosition {
     pub x: i32,
     pub y: i32,
@@ -62,36 +62,36 @@ impl Pathfinder {
         Pathfinder { tilemap: map }
     }
 
+    // Method that checks if a tile lies inside the map
+    pub fn is_within_map(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as i64) < self.tilemap.layers[0].width && (y as i64) < self.tilemap.layers[0].height
+    }
+
     // Method that checks if a tile is walkable
     pub fn tile_is_walkable(&self, x: i32, y: i32) -> bool {
-        if x >= 0 && y >= 0 {
+        if self.is_within_map(x, y) {
             //remember to decrement the tile number.
-            !self.tilemap.tilesets[0].tiles[self.tilemap.layers[0].get_tile_at(x as u32, y as u32) as usize - 1 as usize]
+            !self.tilemap.tilesets[0].tiles[self.tilemap.layers[0].get_tile_at(x as u32, y as u32) as usize - 1_usize]
                 .properties[0]
                 .value
         } else {
-            return false;
+            false
         }
     }
 
     // Method that returns the neighbours of a given tile position
     pub fn get_neighbours(&self, a: &TilePosition) -> Vec<TilePosition> {
-        let mut neighbours = Vec::new();
-        neighbours.push(TilePosition { x: a.x - 1, y: a.y - 1 });
-        neighbours.push(TilePosition { x: a.x - 1, y: a.y });
-        neighbours.push(TilePosition { x: a.x - 1, y: a.y + 1 });
-        neighbours.push(TilePosition { x: a.x, y: a.y - 1 });
-        neighbours.push(TilePosition { x: a.x, y: a.y });
-        neighbours.push(TilePosition { x: a.x, y: a.y + 1 });
-        neighbours.push(TilePosition { x: a.x + 1, y: a.y - 1 });
-        neighbours.push(TilePosition { x: a.x + 1, y: a.y });
-        neighbours.push(TilePosition { x: a.x + 1, y: a.y + 1 });
-        neighbours
-    }
-
-    // Heuristic function that calculates the Manhattan distance between two points
-    fn heuristic(a: &TilePosition, b:&TilePosition) -> i32{
-        (b.x-a.x).abs()+(b.y-a.y).abs()
+        vec![
+            TilePosition { x: a.x - 1, y: a.y - 1 },
+            TilePosition { x: a.x - 1, y: a.y },
+            TilePosition { x: a.x - 1, y: a.y + 1 },
+            TilePosition { x: a.x, y: a.y - 1 },
+            TilePosition { x: a.x, y: a.y },
+            TilePosition { x: a.x, y: a.y + 1 },
+            TilePosition { x: a.x + 1, y: a.y - 1 },
+            TilePosition { x: a.x + 1, y: a.y },
+            TilePosition { x: a.x + 1, y: a.y + 1 },
+        ]
     }
 
     // Method that finds the shortest path between two tiles using the A* algorithm
@@ -121,7 +121,7 @@ impl Pathfinder {
         },
     );
 
-    while let Some(State { cost, position }) = heap.pop() {
+    while let Some(State { cost: _, position }) = heap.pop() {
         if position == target {
             break;
         }
@@ -163,7 +163,7 @@ impl Pathfinder {
     }
     path.reverse();
 
-    return path;
+    path
 }
 }
     
@@ -179,7 +179,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let path = pathfinder.find_path(TilePosition { x: 1, y: 1 }, TilePosition { x: 45, y: 45 });
@@ -187,7 +187,7 @@ mod tests {
         for pos in path {
             println!("Pos.x:{}, Pos.y:{}", pos.x, pos.y);
 
-            assert_eq!(pathfinder.tile_is_walkable(pos.x, pos.y), true);
+            assert!(pathfinder.tile_is_walkable(pos.x, pos.y));
         }
         assert_eq!(false, false);
     }
@@ -196,7 +196,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let path = pathfinder.find_path(TilePosition { x: 1, y: 1 }, TilePosition { x: 1, y: 1 });
@@ -208,7 +208,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let path = pathfinder.find_path(TilePosition { x: 0, y: 1 }, TilePosition { x: 0, y: 9 });
@@ -220,7 +220,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let path = pathfinder.find_path(TilePosition { x: 1, y: 1 }, TilePosition { x: 0, y: 7 });
@@ -231,7 +231,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let path = pathfinder.find_path(TilePosition { x: 0, y: 0 }, TilePosition { x: 5, y: 5 });
@@ -244,7 +244,7 @@ mod tests {
         static PROJECT_DIR: Dir = include_dir!("assets");
         let lib_rs = PROJECT_DIR.get_file("tiledmap.json").unwrap();
         let body = lib_rs.contents_utf8().unwrap();
-        let map_cast: tiledmap::TiledMap = serde_json::from_str(&body).unwrap();
+        let map_cast: tiledmap::TiledMap = serde_json::from_str(body).unwrap();
         let pathfinder: Pathfinder = Pathfinder::new(map_cast);
 
         let duration = bench_find_path(pathfinder);
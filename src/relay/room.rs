@@ -0,0 +1,215 @@
+use super::websocket::Message;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+pub type ConnectionId = u32;
+
+/*The fields of the game's handshake Hello that the relay looks at, see network::handshake in the game. Unknown
+fields are ignored so that the game can add to its hello without a relay update.*/
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct RoomHello {
+    protocol_version: u32,
+    build_hash: String,
+    map_id: String,
+    player_id: u32,
+}
+
+#[derive(Deserialize)]
+enum HandshakeMessage {
+    Hello(RoomHello),
+}
+
+//Requests for the relay itself, see network::lobby in the game. They are answered and never passed on.
+#[derive(Deserialize)]
+enum RelayMessage {
+    ListRooms,
+}
+
+struct Member {
+    room: String,
+    hello: Option<(RoomHello, Vec<u8>)>, //Parsed, and the original text to hand to later arrivals.
+}
+
+/*Rooms and membership, without any sockets so it can be tested on its own. Every message is passed on to the
+other members of the sender's room. Hellos are also remembered, so a peer joining later is sent the hellos of
+everyone already in the room without waiting for them to be resent, and a hello that the room would reject
+(another protocol version, build or map, or a player id already taken) is answered by the relay instead of
+being forwarded, so it cannot break the handshake of the peers already there. Connections to the root path are in
+the unnamed room, which is where the game's room browser asks for the list of rooms.*/
+#[derive(Default)]
+pub struct Relay {
+    members: BTreeMap<ConnectionId, Member>,
+}
+
+//"/12345?x=1" and "12345" are the same room.
+pub fn room_from_path(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    path.trim_start_matches('/').to_string()
+}
+
+impl Relay {
+    pub fn join(&mut self, connection: ConnectionId, path: &str) {
+        let room = room_from_path(path);
+        println!("Connection {} joined room {:?}", connection, room);
+        self.members.insert(connection, Member { room, hello: None });
+    }
+
+    pub fn leave(&mut self, connection: ConnectionId) {
+        if let Some(member) = self.members.remove(&connection) {
+            println!("Connection {} left room {:?}", connection, member.room);
+        }
+    }
+
+    //Returns the messages to send and who to send them to.
+    pub fn receive(&mut self, connection: ConnectionId, message: Message) -> Vec<(ConnectionId, Message)> {
+        let room = match self.members.get(&connection) {
+            Some(member) => member.room.clone(),
+            None => return Vec::new(),
+        };
+        let mut outgoing = Vec::new();
+        if let Message::Text(text) = &message {
+            if let Ok(RelayMessage::ListRooms) = serde_json::from_slice(text) {
+                return vec![(connection, Message::Text(self.room_list().to_string().into_bytes()))];
+            }
+            if let Ok(HandshakeMessage::Hello(hello)) = serde_json::from_slice(text) {
+                if let Some(rejection) = self.check_hello(connection, &room, &hello) {
+                    println!("Rejecting hello from connection {}: {}", connection, rejection);
+                    return vec![(connection, Message::Text(rejection.to_string().into_bytes()))];
+                }
+                if self.members[&connection].hello.is_none() {
+                    outgoing.extend(self.room_hellos(connection, &room).into_iter().map(|text| (connection, Message::Text(text))));
+                }
+                self.members.get_mut(&connection).unwrap().hello = Some((hello, text.clone()));
+            }
+        }
+        for (other, member) in &self.members {
+            if *other != connection && member.room == room {
+                outgoing.push((*other, message.clone()));
+            }
+        }
+        outgoing
+    }
+
+    //A room is playing once its members have moved on from the lobby to the handshake.
+    fn room_list(&self) -> serde_json::Value {
+        let mut rooms: BTreeMap<&str, (usize, bool)> = BTreeMap::new();
+        for member in self.members.values().filter(|member| !member.room.is_empty()) {
+            let room = rooms.entry(&member.room).or_default();
+            room.0 += 1;
+            room.1 |= member.hello.is_some();
+        }
+        let rooms: Vec<serde_json::Value> = rooms
+            .into_iter()
+            .map(|(room_id, (members, playing))| json!({"room_id": room_id, "members": members, "playing": playing}))
+            .collect();
+        json!({ "RoomList": rooms })
+    }
+
+    fn room_hellos(&self, connection: ConnectionId, room: &str) -> Vec<Vec<u8>> {
+        self.members
+            .iter()
+            .filter(|(other, member)| **other != connection && member.room == room)
+            .filter_map(|(_, member)| member.hello.as_ref().map(|(_, text)| text.clone()))
+            .collect()
+    }
+
+    //Builds the same HelloRejected message a peer would send, from the room's side.
+    fn check_hello(&self, connection: ConnectionId, room: &str, hello: &RoomHello) -> Option<serde_json::Value> {
+        let room_hellos: Vec<&RoomHello> = self
+            .members
+            .iter()
+            .filter(|(other, member)| **other != connection && member.room == room)
+            .filter_map(|(_, member)| member.hello.as_ref().map(|(hello, _)| hello))
+            .collect();
+        let reference = room_hellos.first()?;
+        let rejection = if reference.protocol_version != hello.protocol_version {
+            json!({"ProtocolVersionMismatch": {"local": reference.protocol_version, "remote": hello.protocol_version}})
+        } else if reference.build_hash != hello.build_hash {
+            json!({"BuildMismatch": {"local": reference.build_hash, "remote": hello.build_hash}})
+        } else if reference.map_id != hello.map_id {
+            json!({"MapMismatch": {"local": reference.map_id, "remote": hello.map_id}})
+        } else if room_hellos.iter().any(|other| other.player_id == hello.player_id) {
+            json!({ "DuplicatePlayerId": hello.player_id })
+        } else {
+            return None;
+        };
+        Some(json!({ "HelloRejected": {"player_id": hello.player_id, "rejection": rejection} }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(player_id: u32, map_id: &str) -> Message {
+        let hello = json!({"Hello": {
+            "protocol_version": 5,
+            "build_hash": "abc",
+            "map_id": map_id,
+            "player_id": player_id,
+            "player_name": "Engineer",
+            "seed": 1,
+            "role": "Player",
+        }});
+        Message::Text(hello.to_string().into_bytes())
+    }
+
+    #[test]
+    fn messages_stay_in_their_room_test() {
+        let mut relay = Relay::default();
+        relay.join(1, "/12345");
+        relay.join(2, "/12345?name=Paul");
+        relay.join(3, "/other");
+        let frame = Message::Binary(vec![0xEB, 1]);
+        assert_eq!(relay.receive(1, frame.clone()), vec![(2, frame.clone())]);
+        assert_eq!(relay.receive(3, frame), vec![]);
+        relay.leave(2);
+        assert_eq!(relay.receive(1, Message::Text(b"ping".to_vec())), vec![]);
+    }
+
+    #[test]
+    fn later_arrivals_are_sent_the_room_hellos_test() {
+        let mut relay = Relay::default();
+        relay.join(1, "/12345");
+        relay.join(2, "/12345");
+        assert_eq!(relay.receive(1, hello(10, "map")), vec![(2, hello(10, "map"))]);
+        relay.join(3, "/12345");
+        assert_eq!(relay.receive(3, hello(30, "map")), vec![(3, hello(10, "map")), (1, hello(30, "map")), (2, hello(30, "map"))]);
+        //Resent hellos are only forwarded.
+        assert_eq!(relay.receive(3, hello(30, "map")), vec![(1, hello(30, "map")), (2, hello(30, "map"))]);
+    }
+
+    #[test]
+    fn incompatible_hellos_are_answered_by_the_relay_test() {
+        let mut relay = Relay::default();
+        relay.join(1, "/12345");
+        relay.join(2, "/12345");
+        relay.receive(1, hello(10, "map"));
+        let reply = |outgoing: Vec<(ConnectionId, Message)>| match outgoing.as_slice() {
+            [(2, Message::Text(text))] => serde_json::from_slice::<serde_json::Value>(text).unwrap(),
+            outgoing => panic!("Unexpected {:?}", outgoing),
+        };
+        assert_eq!(reply(relay.receive(2, hello(20, "other_map"))), json!({"HelloRejected": {"player_id": 20, "rejection": {"MapMismatch": {"local": "map", "remote": "other_map"}}}}));
+        assert_eq!(reply(relay.receive(2, hello(10, "map"))), json!({"HelloRejected": {"player_id": 10, "rejection": {"DuplicatePlayerId": 10}}}));
+        assert_eq!(relay.receive(2, hello(20, "map")).len(), 2);
+    }
+
+    #[test]
+    fn rooms_are_listed_test() {
+        let mut relay = Relay::default();
+        relay.join(1, "/");
+        relay.join(2, "/12345");
+        relay.join(3, "/12345");
+        relay.join(4, "/lobby");
+        relay.receive(4, hello(40, "map"));
+        let list_rooms = Message::Text(br#""ListRooms""#.to_vec());
+        let expected = json!({"RoomList": [
+            {"room_id": "12345", "members": 2, "playing": false},
+            {"room_id": "lobby", "members": 1, "playing": true},
+        ]});
+        assert_eq!(relay.receive(1, list_rooms.clone()), vec![(1, Message::Text(expected.to_string().into_bytes()))]);
+        //Asked from inside a room, the request is not passed on to the room either.
+        assert_eq!(relay.receive(2, list_rooms).len(), 1);
+    }
+}
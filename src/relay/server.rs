@@ -0,0 +1,74 @@
+use super::room::{ConnectionId, Relay};
+use super::websocket::{ConnectionEvent, WebSocketConnection};
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::Duration;
+
+/*Accepts WebSocket connections and hands their messages to the Relay. Everything runs on one thread over
+non-blocking sockets, sleeping briefly whenever a poll found nothing to do.*/
+pub struct RelayServer {
+    listener: TcpListener,
+    connections: BTreeMap<ConnectionId, WebSocketConnection>,
+    relay: Relay,
+    next_connection_id: ConnectionId,
+}
+
+impl RelayServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(RelayServer { listener, connections: BTreeMap::new(), relay: Relay::default(), next_connection_id: 1 })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            if !self.poll() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    //Returns true if anything happened.
+    pub fn poll(&mut self) -> bool {
+        let mut busy = false;
+        while let Ok((stream, addr)) = self.listener.accept() {
+            busy = true;
+            match WebSocketConnection::new(stream) {
+                Ok(connection) => {
+                    println!("Connection {} from {}", self.next_connection_id, addr);
+                    self.connections.insert(self.next_connection_id, connection);
+                    self.next_connection_id += 1;
+                }
+                Err(e) => println!("Dropping connection from {}: {}", addr, e),
+            }
+        }
+        let mut outgoing = Vec::new();
+        for (connection_id, connection) in self.connections.iter_mut() {
+            while let Some(event) = connection.poll_event() {
+                busy = true;
+                match event {
+                    ConnectionEvent::Opened(path) => self.relay.join(*connection_id, &path),
+                    ConnectionEvent::Message(message) => outgoing.extend(self.relay.receive(*connection_id, message)),
+                    ConnectionEvent::Closed => self.relay.leave(*connection_id),
+                }
+            }
+        }
+        for (connection_id, message) in outgoing {
+            if let Some(connection) = self.connections.get_mut(&connection_id) {
+                connection.send(&message);
+            }
+        }
+        let relay = &mut self.relay;
+        self.connections.retain(|connection_id, connection| {
+            if connection.is_closed() {
+                relay.leave(*connection_id);
+            }
+            !connection.is_closed()
+        });
+        busy
+    }
+}
@@ -0,0 +1,410 @@
+use base64::Engine;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+//Appended to the client's key to form the Sec-WebSocket-Accept header, see RFC 6455 section 4.2.2.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+//Snapshots are the largest game messages and are far smaller than this. Anything bigger closes the connection.
+pub const MAX_MESSAGE_LENGTH: usize = 16 << 20;
+const MAX_REQUEST_LENGTH: usize = 8192;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+//Control frames may not be fragmented and carry at most 125 bytes, RFC 6455 section 5.5.
+const MAX_CONTROL_PAYLOAD_LENGTH: usize = 125;
+
+//Status codes sent in our close frame when the client breaks the protocol, RFC 6455 section 7.4.1.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+
+//Text and binary messages are relayed as they came, the game sends its handshake as text and its frames as binary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(Vec<u8>),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    Opened(String), //The path requested in the upgrade, e.g. "/12345".
+    Message(Message),
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for i in 0..16 {
+            words[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (i, value) in state.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+pub fn accept_key(client_key: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(sha1(format!("{}{}", client_key.trim(), ACCEPT_GUID).as_bytes()))
+}
+
+//Returns the requested path and the response completing the upgrade.
+pub fn parse_upgrade_request(request: &str) -> Result<(String, String), String> {
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let path = match request_line.split(' ').collect::<Vec<&str>>().as_slice() {
+        ["GET", path, version] if version.starts_with("HTTP/") => path.to_string(),
+        _ => return Err(format!("Not a GET request: {}", request_line)),
+    };
+    let mut is_upgrade = false;
+    let mut key = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "upgrade" => is_upgrade = value.trim().eq_ignore_ascii_case("websocket"),
+                "sec-websocket-key" => key = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    match key {
+        Some(key) if is_upgrade => Ok((
+            path,
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            ),
+        )),
+        _ => Err("Not a WebSocket upgrade".to_string()),
+    }
+}
+
+//Server frames are never masked.
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+//Returns the frame and the number of bytes it took, or None if the buffer does not hold a whole frame yet.
+pub fn decode_frame(buffer: &[u8]) -> Result<Option<(Frame, usize)>, String> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    let opcode = buffer[0] & 0x0F;
+    //Clients must mask every frame, RFC 6455 section 5.1.
+    if buffer[1] & 0x80 == 0 {
+        return Err("Unmasked client frame".to_string());
+    }
+    let (length, mut offset) = match buffer[1] & 0x7F {
+        126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() >= 10 => {
+            let mut length = [0u8; 8];
+            length.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(length), 10)
+        }
+        126 | 127 => return Ok(None),
+        length => (length as u64, 2),
+    };
+    if length > MAX_MESSAGE_LENGTH as u64 {
+        return Err(format!("Frame of {} bytes is too large", length));
+    }
+    let length = length as usize;
+    if buffer.len() < offset + 4 + length {
+        return Ok(None);
+    }
+    let mask = [buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]];
+    offset += 4;
+    let payload = buffer[offset..offset + length].iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+    Ok(Some((Frame { fin, opcode, payload }, offset + length)))
+}
+
+enum ConnectionState {
+    Handshaking,
+    Open,
+    Closed,
+}
+
+/*Server side of one WebSocket connection over a non-blocking TcpStream. Completes the upgrade, answers pings,
+reassembles fragmented messages and reports them as ConnectionEvents.*/
+pub struct WebSocketConnection {
+    stream: TcpStream,
+    state: ConnectionState,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    fragments: Option<(u8, Vec<u8>)>, //Opcode and payload of a message still waiting for its final frame.
+    pending_events: VecDeque<ConnectionEvent>,
+}
+
+impl WebSocketConnection {
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(WebSocketConnection {
+            stream,
+            state: ConnectionState::Handshaking,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            fragments: None,
+            pending_events: VecDeque::new(),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, ConnectionState::Closed)
+    }
+
+    pub fn send(&mut self, message: &Message) {
+        if !matches!(self.state, ConnectionState::Open) {
+            return;
+        }
+        let frame = match message {
+            Message::Text(text) => encode_frame(OPCODE_TEXT, text),
+            Message::Binary(data) => encode_frame(OPCODE_BINARY, data),
+        };
+        self.write_buffer.extend_from_slice(&frame);
+        self.flush_writes();
+    }
+
+    pub fn poll_event(&mut self) -> Option<ConnectionEvent> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(event);
+        }
+        if self.is_closed() {
+            return None;
+        }
+        self.flush_writes();
+        self.fill_read_buffer();
+        match self.state {
+            ConnectionState::Handshaking => self.read_upgrade_request(),
+            ConnectionState::Open => self.read_frames(),
+            ConnectionState::Closed => {}
+        }
+        self.pending_events.pop_front()
+    }
+
+    fn close(&mut self, reason: Option<String>) {
+        if self.is_closed() {
+            return;
+        }
+        if let Some(reason) = reason {
+            println!("Closing connection: {}", reason);
+        }
+        self.flush_writes();
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.state = ConnectionState::Closed;
+        self.pending_events.push_back(ConnectionEvent::Closed);
+    }
+
+    //Closes the connection because of the client, telling it why first.
+    fn fail(&mut self, status: u16, reason: String) {
+        self.write_buffer.extend_from_slice(&encode_frame(OPCODE_CLOSE, &status.to_be_bytes()));
+        self.close(Some(reason));
+    }
+
+    fn flush_writes(&mut self) {
+        while !self.write_buffer.is_empty() {
+            match self.stream.write(&self.write_buffer) {
+                Ok(0) => return self.close(None),
+                Ok(written) => {
+                    self.write_buffer.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return self.close(Some(e.to_string())),
+            }
+        }
+    }
+
+    fn fill_read_buffer(&mut self) {
+        let mut chunk = [0u8; 4096];
+        while !self.is_closed() {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return self.close(None),
+                Ok(read) => self.read_buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return self.close(Some(e.to_string())),
+            }
+        }
+    }
+
+    fn read_upgrade_request(&mut self) {
+        let end = match self.read_buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None if self.read_buffer.len() > MAX_REQUEST_LENGTH => return self.close(Some("Upgrade request too long".to_string())),
+            None => return,
+        };
+        let request = String::from_utf8_lossy(&self.read_buffer[..end]).into_owned();
+        self.read_buffer.drain(..end);
+        match parse_upgrade_request(&request) {
+            Ok((path, response)) => {
+                self.write_buffer.extend_from_slice(response.as_bytes());
+                self.state = ConnectionState::Open;
+                self.flush_writes();
+                self.pending_events.push_back(ConnectionEvent::Opened(path));
+                //The client may have sent its first frames right behind the request.
+                self.read_frames();
+            }
+            Err(e) => {
+                self.write_buffer.extend_from_slice(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                self.close(Some(e));
+            }
+        }
+    }
+
+    fn read_frames(&mut self) {
+        while !self.is_closed() {
+            let (frame, length) = match decode_frame(&self.read_buffer) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => return,
+                Err(e) => return self.fail(CLOSE_PROTOCOL_ERROR, e),
+            };
+            self.read_buffer.drain(..length);
+            self.handle_frame(frame);
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        let is_control = frame.opcode & 0x8 != 0;
+        if is_control && (!frame.fin || frame.payload.len() > MAX_CONTROL_PAYLOAD_LENGTH) {
+            return self.fail(CLOSE_PROTOCOL_ERROR, format!("Fragmented or oversized control frame with opcode {}", frame.opcode));
+        }
+        match frame.opcode {
+            OPCODE_PING => {
+                self.write_buffer.extend_from_slice(&encode_frame(OPCODE_PONG, &frame.payload));
+                self.flush_writes();
+            }
+            OPCODE_PONG => {}
+            OPCODE_CLOSE => {
+                self.write_buffer.extend_from_slice(&encode_frame(OPCODE_CLOSE, &frame.payload));
+                self.close(None);
+            }
+            OPCODE_TEXT | OPCODE_BINARY if self.fragments.is_none() => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                self.finish_message(frame.fin);
+            }
+            OPCODE_CONTINUATION if self.fragments.is_some() => {
+                if let Some((_, payload)) = &mut self.fragments {
+                    payload.extend_from_slice(&frame.payload);
+                }
+                self.finish_message(frame.fin);
+            }
+            opcode => self.fail(CLOSE_PROTOCOL_ERROR, format!("Unexpected frame with opcode {}", opcode)),
+        }
+    }
+
+    fn finish_message(&mut self, fin: bool) {
+        match &self.fragments {
+            Some((_, payload)) if payload.len() > MAX_MESSAGE_LENGTH => return self.close(Some("Message too large".to_string())),
+            _ if !fin => return,
+            _ => {}
+        }
+        let message = match self.fragments.take() {
+            Some((OPCODE_TEXT, payload)) if std::str::from_utf8(&payload).is_err() => {
+                return self.fail(CLOSE_INVALID_PAYLOAD, "Text message is not valid UTF-8".to_string())
+            }
+            Some((OPCODE_TEXT, payload)) => Message::Text(payload),
+            Some((_, payload)) => Message::Binary(payload),
+            None => return,
+        };
+        self.pending_events.push_back(ConnectionEvent::Message(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![first_byte, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn sha1_test() {
+        let hex = |digest: [u8; 20]| digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn upgrade_request_test() {
+        //The example from RFC 6455 section 1.3.
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let request = "GET /12345 HTTP/1.1\r\nHost: 127.0.0.1:3012\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let (path, response) = parse_upgrade_request(request).unwrap();
+        assert_eq!(path, "/12345");
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(parse_upgrade_request("GET /12345 HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn frames_test() {
+        let frame = masked_frame(0x81, b"hello");
+        assert_eq!(decode_frame(&frame[..4]), Ok(None));
+        assert_eq!(decode_frame(&frame), Ok(Some((Frame { fin: true, opcode: OPCODE_TEXT, payload: b"hello".to_vec() }, frame.len()))));
+        assert!(decode_frame(&encode_frame(OPCODE_TEXT, b"hello")).is_err());
+
+        assert_eq!(encode_frame(OPCODE_BINARY, &[1, 2]), vec![0x82, 2, 1, 2]);
+        let long = encode_frame(OPCODE_BINARY, &[0; 300]);
+        assert_eq!(&long[..4], &[0x82, 126, 1, 44]);
+        assert_eq!(long.len(), 304);
+    }
+}
@@ -0,0 +1,45 @@
+/*Relay server the game connects to. Peers join a room through the WebSocket URL path (ws://host:3012/12345) and
+every message is passed on to the other peers in the same room. The server takes no part in the game itself.*/
+mod relay {
+    pub mod room;
+    pub mod server;
+    pub mod websocket;
+}
+use relay::server::RelayServer;
+
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 3012;
+
+//Accepts "--host 0.0.0.0 --port 3012", with "--key=value" also allowed, or ENGINEERS_RELAY_HOST/ENGINEERS_RELAY_PORT.
+fn bind_address() -> String {
+    let mut host = std::env::var("ENGINEERS_RELAY_HOST").unwrap_or_else(|_| DEFAULT_HOST.to_string());
+    let mut port = std::env::var("ENGINEERS_RELAY_PORT").unwrap_or_else(|_| DEFAULT_PORT.to_string());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (arg.as_str(), iter.next().cloned()),
+        };
+        match (key, value) {
+            ("--host", Some(value)) => host = value,
+            ("--port", Some(value)) => port = value,
+            (key, _) => println!("Ignoring argument: {}", key),
+        }
+    }
+    format!("{}:{}", host, port)
+}
+
+fn main() {
+    let address = bind_address();
+    let mut server = match RelayServer::bind(&address) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Cannot listen on {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+    //Integration tests bind port 0 and read the real port from this line.
+    println!("Relay listening on {}", server.local_addr().unwrap());
+    server.run();
+}
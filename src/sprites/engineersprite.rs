@@ -1,77 +1,48 @@
 use super::super::pathfinding::pathfinder::TilePosition;
-use super::sprite::{grid_to_world_coords, world_to_grid_coords, Sprite};
-use macroquad::{color, experimental::animation::AnimatedSprite, prelude::*};
-use macroquad::{
-    experimental::animation::{Animation},
-};
+use super::sprite::{grid_to_world_coords, Sprite};
+use crate::model::checksum::StateHasher;
+use macroquad::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::network::wireformat::de_bin_vec;
+use nanoserde::{SerBin, DeBin, DeBinErr};
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, SerBin, Clone)]
 pub struct Engineer {
-    pub texture: Texture2D,
-    pub animated_sprite: AnimatedSprite,
-    pub selected_texture: Texture2D,
     pub x: i32,
     pub y: i32,
     pub current_path: Vec<TilePosition>,
     pub previous_position: TilePosition,
     pub uuid: u32,
+    pub owner: u32, //player_id of the player allowed to command this unit.
     pub selected: bool,
+    pub direction: usize,
     movement_tick_counter: i32,
     ticks_to_move_one_square: i32,
 }
 
+//Units arrive in snapshots, so the length of the path is checked rather than trusted, see de_bin_vec.
+impl DeBin for Engineer {
+    fn de_bin(o: &mut usize, d: &[u8]) -> Result<Self, DeBinErr> {
+        Ok(Engineer {
+            x: DeBin::de_bin(o, d)?,
+            y: DeBin::de_bin(o, d)?,
+            current_path: de_bin_vec(o, d)?,
+            previous_position: DeBin::de_bin(o, d)?,
+            uuid: DeBin::de_bin(o, d)?,
+            owner: DeBin::de_bin(o, d)?,
+            selected: DeBin::de_bin(o, d)?,
+            direction: DeBin::de_bin(o, d)?,
+            movement_tick_counter: DeBin::de_bin(o, d)?,
+            ticks_to_move_one_square: DeBin::de_bin(o, d)?,
+        })
+    }
+}
+
 impl Engineer {
 
-    pub fn new(x: i32, y: i32, width: i32, height: i32, spritesheet: Texture2D, uuid: u32, selected_texture: Texture2D ) -> Self 
+    pub fn new(x: i32, y: i32, _width: i32, _height: i32, uuid: u32, owner: u32) -> Self 
     {
-        Self {texture: spritesheet, animated_sprite: AnimatedSprite::new(
-            64,
-            64,
-            &[Animation {
-                name: "N".to_string(),
-                row: 0,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "NW".to_string(),
-                row: 1,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "W".to_string(),
-                row: 2,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "SW".to_string(),
-                row: 3,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "S".to_string(),
-                row: 4,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "SE".to_string(),
-                row: 5,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "E".to_string(),
-                row: 6,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            },Animation {
-                name: "NE".to_string(),
-                row: 7,
-                frames: 17, //18 frames including original frame which we want to skip.
-                fps: 30,
-            }],
-            true,
-        ), 
-        selected_texture:selected_texture,
-    x: x, y: y, current_path: Vec::new(), previous_position: TilePosition{x:x,y:y},  uuid:uuid, selected:false,movement_tick_counter: 0, ticks_to_move_one_square: 10}
+        Self {x, y, current_path: Vec::new(), previous_position: TilePosition{x,y},  uuid, owner, selected:false, direction: 0, movement_tick_counter: 0, ticks_to_move_one_square: 10}
     }
 
     pub fn get_animation_direction(current_position: &TilePosition, next_position: &TilePosition) -> usize
@@ -80,32 +51,32 @@ impl Engineer {
         let y_differential = current_position.y-next_position.y;
         if x_differential==1 && y_differential==1
         {
-            return 0;
+            0
         }else if x_differential==1 && y_differential==0
         {
-            return 1;
+            1
         }else if x_differential==1 && y_differential==-1
         {
-            return 2;
+            2
         }else if x_differential==0 && y_differential==-1
         {
-            return 3;
+            3
         }else if x_differential==-1 && y_differential==-1
         {
-            return 4;
+            4
         }else if x_differential==-1 && y_differential==0
         {
-            return 5;
+            5
         }else if x_differential==-1 && y_differential==1
         {
-            return 6;
+            6
         }else if x_differential==0 && y_differential==1
         {
-            return 7;
+            7
         }
         else
         {
-            return 3;
+            3
         }
     }
     pub fn is_within_bounds(&self, coords: Vec2) -> bool {
@@ -118,14 +89,14 @@ impl Engineer {
         {
             return true;
         }
-        return false;
+        false
     }
     
-    pub fn tick(&mut self, time: u32) {
+    pub fn tick(&mut self, _time: u32) {
         self.handle_tick();
     }
     pub fn update_path(&mut self, path: Vec<TilePosition>) {
-        if self.current_path.len() > 0 {
+        if !self.current_path.is_empty() {
             self.previous_position = TilePosition{x:self.get_tile_pos().x as i32, y:self.get_tile_pos().y as i32};
         }
         self.movement_tick_counter=0;
@@ -133,14 +104,13 @@ impl Engineer {
     }
 
     fn handle_tick(&mut self) {
-        self.animated_sprite.set_animation(Engineer::get_animation_direction(&TilePosition { x: self.x, y: self.y }, &self.current_path.get(0).or_else(||Some(&self.previous_position
-        )).unwrap()));
+        self.direction = Engineer::get_animation_direction(&TilePosition { x: self.x, y: self.y }, self.current_path.first().unwrap_or(&self.previous_position));
 
         self.movement_tick_counter+=1;
-        if(self.movement_tick_counter==self.ticks_to_move_one_square)
+        if self.movement_tick_counter==self.ticks_to_move_one_square
         {
             self.movement_tick_counter=0;
-            if(self.current_path.len()>0)
+            if !self.current_path.is_empty() 
             {
                 self.previous_position= TilePosition{x:self.x,y: self.y};
                 self.x=self.current_path[0].x;
@@ -149,6 +119,33 @@ impl Engineer {
             }
         }
     }
+
+    //Only simulation data is hashed, selection is local to each player.
+    pub fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.owner);
+        hasher.write_i32(self.x);
+        hasher.write_i32(self.y);
+        hasher.write_i32(self.previous_position.x);
+        hasher.write_i32(self.previous_position.y);
+        hasher.write_u32(self.current_path.len() as u32);
+        for position in &self.current_path {
+            hasher.write_i32(position.x);
+            hasher.write_i32(position.y);
+        }
+        hasher.write_i32(self.movement_tick_counter);
+        hasher.write_i32(self.ticks_to_move_one_square);
+    }
+
+    //Tile position between the current square and the next one on the path, used for smooth rendering.
+    pub fn get_interpolated_tile_pos(&self) -> Vec2 {
+        if !self.current_path.is_empty() {
+            let x_offset: f32 = (self.current_path[0].x as f32 - self.x as f32) * (self.movement_tick_counter as f32 / self.ticks_to_move_one_square as f32);
+            let y_offset: f32 = (self.current_path[0].y as f32 - self.y as f32) * (self.movement_tick_counter as f32/ self.ticks_to_move_one_square as f32);
+            Vec2{x:self.x as f32+ x_offset, y: self.y as f32 + y_offset}
+        } else {
+            Vec2{x:self.x as f32, y:self.y as f32}
+        }
+    }
 }
 
 
@@ -161,40 +158,4 @@ impl Sprite for Engineer {
     fn get_tile_pos(&self) -> Vec2 {
        vec2(self.x as f32, self.y as f32)
     }
-    fn draw(&mut self) {
-        self.animated_sprite.update();
-        let render_location: Vec2;
-        if (self.current_path.get(0).is_some()) {
-            let x_offset: f32 = (self.current_path[0].x as f32 - self.x as f32) * (self.movement_tick_counter as f32 / self.ticks_to_move_one_square as f32);
-            let y_offset: f32 = (self.current_path[0].y as f32 - self.y as f32) * (self.movement_tick_counter as f32/ self.ticks_to_move_one_square as f32);
-            render_location = grid_to_world_coords(Vec2{x:self.x as f32+ x_offset, y: self.y as f32 + y_offset});
-        } else {
-            render_location = grid_to_world_coords(Vec2{x:self.x as f32, y:self.y as f32});
-        }
-        draw_texture_ex(
-            self.texture,
-            render_location.x as f32,
-            render_location.y as f32- 5.,
-            color::WHITE,
-            DrawTextureParams {
-                source: Some(self.animated_sprite.frame().source_rect),
-                dest_size: Some(self.animated_sprite.frame().dest_size),
-                ..Default::default()
-            },
-        );
-        if(self.selected)
-        {
-            draw_texture_ex(
-                self.selected_texture,
-                render_location.x as f32,
-                render_location.y as f32,
-                color::WHITE,
-                DrawTextureParams {
-                    dest_size: Some(vec2(64.0, 64.0)),
-                    source: Some(Rect::new(0., 0., 64., 64.)),
-                    ..Default::default()
-                },
-            );
-        }
-    }
 }
@@ -2,18 +2,29 @@ use macroquad::prelude::*;
 use super::engineersprite::Engineer;
 use super::mechsprite::Mech;
 use super::tilesprite::TileSprite;
+use serde::{Serialize, Deserialize};
+use nanoserde::{SerBin, DeBin};
 pub trait Sprite {
     fn get_zindex(&self) -> u32;
     fn get_tile_pos(&self) -> Vec2;
-    fn draw(&mut self);
 }
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, SerBin, DeBin, Clone)]
 pub enum SpriteID {
     Engineer(Engineer),
     Mech(Mech),
     Tile(TileSprite)
 }
 
+impl SpriteID {
+    pub fn get_uuid(&self) -> u32 {
+        match self {
+            SpriteID::Engineer(engineer_entity) => engineer_entity.uuid,
+            SpriteID::Mech(mech_entity) => mech_entity.uuid,
+            SpriteID::Tile(tile_entity) => tile_entity.uuid,
+        }
+    }
+}
+
 impl Sprite for SpriteID
 {
 
@@ -31,20 +42,13 @@ fn get_tile_pos(&self) -> macroquad::math::Vec2 {
         SpriteID::Tile(tile_entity) => tile_entity.get_tile_pos()
     }
  }
-fn draw(&mut self) {
-    match self{
-        SpriteID::Engineer(engineer_entity) => engineer_entity.draw(),
-        SpriteID::Mech(mech_entity) => mech_entity.draw(),
-        SpriteID::Tile(tile_entity) => tile_entity.draw()
-    }
- }
 
 }
 
 pub fn grid_to_world_coords(grid_pos: Vec2) -> Vec2 {
     vec2(
-        ((grid_pos.x as f32 * 64. / 2.0) - (grid_pos.y as f32 * 64. / 2.0)) + 8.,
-        ((grid_pos.y as f32 * 32. / 2.0) + (grid_pos.x as f32 * 32. / 2.0)) - 8.,
+        ((grid_pos.x * 64. / 2.0) - (grid_pos.y * 64. / 2.0)) + 8.,
+        ((grid_pos.y * 32. / 2.0) + (grid_pos.x * 32. / 2.0)) - 8.,
     )
 }
 
@@ -1,7 +1,14 @@
 use serde::{Deserialize, Serialize};
 use super::sprites::tilesprite::TileSprite;
-use macroquad::{texture::Texture2D, rand};
 use super::sprites::sprite::{SpriteID};
+use super::model::checksum::StateHasher;
+use super::model::idallocator::IdAllocator;
+use include_dir::include_dir;
+use include_dir::Dir;
+
+//Maps are the Tiled JSON files at the top of the assets directory, named by their file name.
+static ASSETS_DIR: Dir = include_dir!("assets");
+pub const DEFAULT_MAP: &str = "tiledmap.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TiledMap {
@@ -83,24 +90,57 @@ pub struct Property {
     pub value: bool,
 }
 
-pub fn get_tilemap_spritelist(tileset: Texture2D, tilemap: &TiledMap) -> std::collections::HashMap<u32,SpriteID> {
+//Identifies a map by name and content, so peers with an edited copy of the same file are told apart.
+pub fn get_map_id(name: &str, contents: &[u8]) -> String {
+    let mut hasher = StateHasher::new();
+    hasher.write_bytes(contents);
+    format!("{}:{:016x}", name, hasher.finish())
+}
+
+pub fn get_map_names() -> Vec<String> {
+    let mut names: Vec<String> = ASSETS_DIR
+        .files()
+        .filter(|file| file.path().extension().is_some_and(|extension| extension == "json"))
+        .map(|file| file.path().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+//Returns the map together with its map id.
+pub fn load_map(name: &str) -> Option<(TiledMap, String)> {
+    let body = ASSETS_DIR.get_file(name)?.contents_utf8()?;
+    let tilemap: TiledMap = serde_json::from_str(body).ok()?;
+    Some((tilemap, get_map_id(name, body.as_bytes())))
+}
+
+//The map a map id was computed from, None when it is missing or has been edited since.
+pub fn load_map_by_id(map_id: &str) -> Option<TiledMap> {
+    let name = &map_id[..map_id.rfind(':')?];
+    match load_map(name)? {
+        (tilemap, loaded_map_id) if loaded_map_id == map_id => Some(tilemap),
+        _ => None,
+    }
+}
+
+pub fn get_tilemap_spritelist(tilemap: &TiledMap) -> std::collections::HashMap<u32,SpriteID> {
     let mut sprite_store: std::collections::HashMap<u32,SpriteID> = std::collections::HashMap::new();
-    for layer_num in 0 as usize..tilemap.layers.len() as usize {
-        for y in 0 as u32..tilemap.height as u32 {
-            for x in 0 as u32..tilemap.width as u32 {
-                let uuid: u32 = rand::rand();
+    let mut id_allocator = IdAllocator::for_tiles();
+    for layer_num in 0_usize..tilemap.layers.len() {
+        for y in 0_u32..tilemap.height as u32 {
+            for x in 0_u32..tilemap.width as u32 {
+                let uuid: u32 = id_allocator.next_id();
                 let tile_sprite: SpriteID = SpriteID::Tile(TileSprite {
                     layer: layer_num as u32,
-                    x: x,
-                    y: y,
-                    texture: tileset,
+                    x,
+                    y,
                     frame_number: tilemap.layers[layer_num].data
                         [(y * tilemap.layers[layer_num].width as u32 + x) as usize]
                         as u32
                         - 1,
                     width: 64.0,
                     height: 32.0,
-                    uuid: uuid,
+                    uuid,
                 });
                 sprite_store.insert(uuid, tile_sprite);
             }
@@ -108,3 +148,22 @@ pub fn get_tilemap_spritelist(tileset: Texture2D, tilemap: &TiledMap) -> std::co
     }
     sprite_store
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_are_found_in_assets_test() {
+        assert!(get_map_names().contains(&DEFAULT_MAP.to_string()));
+        let (tilemap, map_id) = load_map(DEFAULT_MAP).unwrap();
+        assert!(tilemap.width > 0 && tilemap.height > 0);
+        assert!(map_id.starts_with("tiledmap.json:"));
+        assert!(load_map("missing.json").is_none());
+
+        assert!(load_map_by_id(&map_id).is_some());
+        assert!(load_map_by_id(&get_map_id(DEFAULT_MAP, b"edited")).is_none());
+        assert!(load_map_by_id("missing.json:0000000000000000").is_none());
+        assert!(load_map_by_id(DEFAULT_MAP).is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::sprites::engineersprite::Engineer;
+use crate::sprites::sprite::grid_to_world_coords;
+use macroquad::{color, experimental::animation::AnimatedSprite, prelude::*};
+use macroquad::{
+    experimental::animation::{Animation},
+};
+
+//Presentation state for a single Engineer. The simulation data lives in Engineer, this only holds what is needed to draw it.
+pub struct EngineerView {
+    pub animated_sprite: AnimatedSprite,
+}
+
+impl EngineerView {
+    pub fn new() -> Self
+    {
+        let animated_sprite = AnimatedSprite::new(
+            64,
+            64,
+            &[Animation {
+                name: "N".to_string(),
+                row: 0,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "NW".to_string(),
+                row: 1,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "W".to_string(),
+                row: 2,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "SW".to_string(),
+                row: 3,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "S".to_string(),
+                row: 4,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "SE".to_string(),
+                row: 5,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "E".to_string(),
+                row: 6,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            },Animation {
+                name: "NE".to_string(),
+                row: 7,
+                frames: 17, //18 frames including original frame which we want to skip.
+                fps: 30,
+            }],
+            true,
+        );
+        Self { animated_sprite }
+    }
+
+    pub fn draw(&mut self, engineer: &Engineer, texture: Texture2D, selected_texture: Texture2D, tint: Color) {
+        self.animated_sprite.set_animation(engineer.direction);
+        self.animated_sprite.update();
+        let render_location: Vec2 = grid_to_world_coords(engineer.get_interpolated_tile_pos());
+        draw_texture_ex(
+            texture,
+            render_location.x,
+            render_location.y- 5.,
+            tint,
+            DrawTextureParams {
+                source: Some(self.animated_sprite.frame().source_rect),
+                dest_size: Some(self.animated_sprite.frame().dest_size),
+                ..Default::default()
+            },
+        );
+        if engineer.selected
+        {
+            draw_texture_ex(
+                selected_texture,
+                render_location.x,
+                render_location.y,
+                color::WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(64.0, 64.0)),
+                    source: Some(Rect::new(0., 0., 64., 64.)),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use super::engineerview::EngineerView;
+use super::mechview::MechView;
+use super::tileview::draw_tile;
+use crate::model::gamestate::GameState;
+use crate::model::idallocator::IdAllocator;
+use crate::network::handshake::MAX_PLAYERS;
+use crate::sprites::sprite::{Sprite, SpriteID};
+use include_dir::include_dir;
+use include_dir::Dir;
+use macroquad::color::Color;
+use macroquad::texture::Texture2D;
+use std::collections::HashMap;
+
+static ASSETS_DIR: Dir = include_dir!("assets");
+
+//Units are tinted with their player's slot colour, the faction picked in the lobby.
+pub const SLOT_COLOURS: [Color; MAX_PLAYERS] = [
+    Color::new(1.0, 0.55, 0.55, 1.0),
+    Color::new(0.55, 0.7, 1.0, 1.0),
+    Color::new(0.6, 1.0, 0.6, 1.0),
+    Color::new(1.0, 0.95, 0.5, 1.0),
+    Color::new(0.85, 0.6, 1.0, 1.0),
+    Color::new(1.0, 0.75, 0.45, 1.0),
+    Color::new(0.5, 1.0, 0.95, 1.0),
+    Color::new(0.75, 0.75, 0.75, 1.0),
+];
+
+pub fn unit_colour(uuid: u32) -> Color {
+    match IdAllocator::player_slot_of(uuid) {
+        Some(slot) if (slot as usize) < MAX_PLAYERS => SLOT_COLOURS[slot as usize],
+        _ => macroquad::color::WHITE,
+    }
+}
+
+pub struct SpriteTextures {
+    pub tileset: Texture2D,
+    pub engineer: Texture2D,
+    pub mech_idle: Texture2D,
+    pub mech_walk: Texture2D,
+    pub selected: Texture2D,
+}
+
+impl SpriteTextures {
+    //Requires a graphics context, so this must only be called once the window exists.
+    pub fn load() -> Self {
+        Self {
+            tileset: SpriteTextures::load_texture("tileset.png"),
+            engineer: SpriteTextures::load_texture("spritesheet_rock.png"),
+            mech_idle: SpriteTextures::load_texture("spritesheet_mech_idle.png"),
+            mech_walk: SpriteTextures::load_texture("spritesheet_mech_walk.png"),
+            selected: SpriteTextures::load_texture("selected.png"),
+        }
+    }
+
+    fn load_texture(name: &str) -> Texture2D {
+        Texture2D::from_file_with_format(ASSETS_DIR.get_file(name).unwrap().contents(), None)
+    }
+}
+
+/*View layer. Reads the simulation state held in GameState and draws it, keeping per-unit animation state keyed by sprite uuid.*/
+pub struct GameView {
+    textures: SpriteTextures,
+    engineer_views: HashMap<u32, EngineerView>,
+    mech_views: HashMap<u32, MechView>,
+}
+
+impl GameView {
+    pub fn new() -> Self {
+        Self {
+            textures: SpriteTextures::load(),
+            engineer_views: HashMap::new(),
+            mech_views: HashMap::new(),
+        }
+    }
+
+    pub fn render(&mut self, game_state: &GameState) {
+        /*Drop views of units which no longer exist (e.g. after a rollback)*/
+        self.engineer_views.retain(|uuid, _| game_state.sprite_map.contains_key(uuid));
+        self.mech_views.retain(|uuid, _| game_state.sprite_map.contains_key(uuid));
+
+        for uuid in GameView::sort_by_z_index(game_state) {
+            match game_state.sprite_map.get(&uuid).unwrap() {
+                SpriteID::Engineer(engineer_entity) => {
+                    self.engineer_views
+                        .entry(uuid)
+                        .or_insert_with(EngineerView::new)
+                        .draw(engineer_entity, self.textures.engineer, self.textures.selected, unit_colour(uuid));
+                }
+                SpriteID::Mech(mech_entity) => {
+                    self.mech_views
+                        .entry(uuid)
+                        .or_insert_with(MechView::new)
+                        .draw(mech_entity, self.textures.mech_idle, self.textures.mech_walk, self.textures.selected, unit_colour(uuid));
+                }
+                SpriteID::Tile(tile_entity) => draw_tile(tile_entity, self.textures.tileset),
+            }
+        }
+    }
+
+    pub fn sort_by_z_index(game_state: &GameState) -> Vec<u32> {
+        let mut sprite_uuid_copy = game_state.sprite_uuid_list.clone();
+        sprite_uuid_copy.sort_by(|a, b| {
+            let ordering = game_state
+                .sprite_map
+                .get(a)
+                .unwrap()
+                .get_zindex()
+                .cmp(&game_state.sprite_map.get(b).unwrap().get_zindex());
+            if ordering == std::cmp::Ordering::Equal {
+                let mut a_val = 0;
+                let mut b_val = 0;
+                if let SpriteID::Tile(_) = game_state.sprite_map.get(a).unwrap() {
+                    a_val = 1;
+                }
+                if let SpriteID::Tile(_) = game_state.sprite_map.get(b).unwrap() {
+                    b_val = 1;
+                }
+                a_val.cmp(&b_val)
+            } else {
+                ordering
+            }
+        });
+        sprite_uuid_copy
+    }
+}
@@ -0,0 +1,153 @@
+use crate::sprites::mechsprite::Mech;
+use crate::sprites::sprite::grid_to_world_coords;
+use macroquad::{color, experimental::animation::AnimatedSprite, prelude::*};
+use macroquad::{
+    experimental::animation::{Animation},
+};
+
+//Presentation state for a single Mech. The simulation data lives in Mech, this only holds what is needed to draw it.
+pub struct MechView {
+    pub walking_animation: AnimatedSprite,
+    pub idle_animation: AnimatedSprite,
+}
+
+impl MechView {
+    pub fn new() -> Self
+    {
+        let idle_animation =  AnimatedSprite::new(
+            64,
+            64,
+            &[Animation {
+                name: "N".to_string(),
+                row: 3,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "NW".to_string(),
+                row: 4,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "W".to_string(),
+                row: 5,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "SW".to_string(),
+                row: 6,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "S".to_string(),
+                row: 7,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "SE".to_string(),
+                row: 0,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "E".to_string(),
+                row: 1,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "NE".to_string(),
+                row: 2,
+                frames: 40, //18 frames including original frame which we want to skip.
+                fps: 40,
+            }],
+            true,
+        );
+        let walk_animation =  AnimatedSprite::new(
+            64,
+            64,
+            &[Animation {
+                name: "N".to_string(),
+                row: 3,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "NW".to_string(),
+                row: 4,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "W".to_string(),
+                row: 5,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "SW".to_string(),
+                row: 6,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "S".to_string(),
+                row: 7,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "SE".to_string(),
+                row: 0,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "E".to_string(),
+                row: 1,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            },Animation {
+                name: "NE".to_string(),
+                row: 2,
+                frames: 23, //18 frames including original frame which we want to skip.
+                fps: 40,
+            }],
+            true,
+        );
+        Self { idle_animation, walking_animation: walk_animation }
+    }
+
+    pub fn draw(&mut self, mech: &Mech, idle_texture: Texture2D, walking_texture: Texture2D, selected_texture: Texture2D, tint: Color) {
+        self.walking_animation.set_animation(mech.direction);
+        self.idle_animation.set_animation(mech.direction);
+        //Majority of time this will be the walking state
+        let mut active_anim: &mut AnimatedSprite = &mut self.idle_animation;
+        let mut active_texture: Texture2D = idle_texture;
+        
+        if mech.is_walking() // In the Walking State
+        {
+            active_anim = &mut self.walking_animation;
+            active_texture = walking_texture;
+
+        }
+        active_anim.update();
+        let render_location: Vec2 = grid_to_world_coords(mech.get_interpolated_tile_pos());
+        draw_texture_ex(
+            active_texture,
+            render_location.x,
+            render_location.y,
+            tint,
+            DrawTextureParams {
+                source: Some(active_anim.frame().source_rect),
+                dest_size: Some(active_anim.frame().dest_size),
+                ..Default::default()
+            },
+        );
+        if mech.selected
+        {
+            draw_texture_ex(
+                selected_texture,
+                render_location.x,
+                render_location.y,
+                color::WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(64.0, 64.0)),
+                    source: Some(Rect::new(0., 0., 64., 64.)),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
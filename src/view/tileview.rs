@@ -0,0 +1,38 @@
+use crate::sprites::tilesprite::TileSprite;
+use macroquad::{
+    prelude::*,
+};
+
+pub fn draw_tile(tile: &TileSprite, tileset: Texture2D) {
+    let pos = vec2(
+        (tile.x as f32 * tile.width / 2.0) - (tile.y as f32 * tile.width / 2.0),
+        (tile.y as f32 * tile.height / 2.0) + (tile.x as f32 * tile.height / 2.0),
+    );
+    let spr_rect = sprite_rect(tile.frame_number);
+    draw_texture_ex(
+        tileset,
+        pos.x,
+        pos.y,
+        WHITE,
+        DrawTextureParams {
+            dest_size: Some(vec2(64.0, 64.0)),
+            source: Some(Rect::new(
+                spr_rect.x - 1.0,
+                spr_rect.y - 1.0 + 16.0,
+                spr_rect.w,
+                spr_rect.h,
+            )),
+            ..Default::default()
+        },
+    );
+}
+
+fn sprite_rect(frame_number: u32) -> Rect {
+    let ix = frame_number;
+    let sw = 64_f32;
+    let sh = 64_f32;
+    let sx = (ix % 20) as f32 * (sw + 0 as f32) + 0 as f32;
+    let sy = (ix / 20) as f32 * (sh + 0 as f32) + 0 as f32;
+    // TODO: configure tiles margin
+    Rect::new(sx + 1.1, sy + 1.1, sw - 2.2, sh - 2.2)
+}
@@ -0,0 +1,219 @@
+//Runs the engineers-relay binary on a free port and talks to it over real WebSocket connections.
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+struct RelayProcess {
+    child: Child,
+    port: u16,
+}
+
+impl RelayProcess {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_engineers-relay"))
+            .args(["--host", "127.0.0.1", "--port", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let port = line.trim().rsplit(':').next().unwrap().parse().unwrap();
+        //Keep draining the log so the relay never blocks on a full pipe.
+        std::thread::spawn(move || std::io::copy(&mut stdout, &mut std::io::sink()));
+        RelayProcess { child, port }
+    }
+}
+
+impl Drop for RelayProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+//Minimal client side of RFC 6455: masked frames out, unmasked frames in, no fragmentation.
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    fn connect(port: u16, path: &str) -> Self {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, port
+        )
+        .unwrap();
+        let mut client = Client { stream, buffer: Vec::new() };
+        let end = loop {
+            if let Some(end) = client.buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            assert!(client.fill(), "No upgrade response");
+        };
+        let response = String::from_utf8(client.buffer.drain(..end).collect()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "), "{}", response);
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), "{}", response);
+        client
+    }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) {
+        self.send_frame(0x80 | opcode, payload);
+    }
+
+    //`first_byte` holds the fin bit and the opcode.
+    fn send_frame(&mut self, first_byte: u8, payload: &[u8]) {
+        let mask = [7, 1, 2, 9];
+        let mut frame = vec![first_byte];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn send_text(&mut self, text: &str) {
+        self.send(0x1, text.as_bytes());
+    }
+
+    //Returns false if nothing arrived before the read timeout.
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => false,
+            Ok(read) => {
+                self.buffer.extend_from_slice(&chunk[..read]);
+                true
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => false,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<(u8, Vec<u8>)> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let (length, offset) = match self.buffer[1] {
+            126 if self.buffer.len() >= 4 => (u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as usize, 4),
+            126 => return None,
+            length => (length as usize, 2),
+        };
+        if self.buffer.len() < offset + length {
+            return None;
+        }
+        let opcode = self.buffer[0] & 0x0F;
+        let payload = self.buffer[offset..offset + length].to_vec();
+        self.buffer.drain(..offset + length);
+        Some((opcode, payload))
+    }
+
+    //Waits up to a second for the next frame.
+    fn receive(&mut self) -> Option<(u8, Vec<u8>)> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if let Some(frame) = self.take_frame() {
+                return Some(frame);
+            }
+            self.fill();
+        }
+        None
+    }
+
+    fn receive_text(&mut self) -> Option<String> {
+        match self.receive() {
+            Some((0x1, payload)) => Some(String::from_utf8(payload).unwrap()),
+            other => panic!("Expected a text frame, got {:?}", other),
+        }
+    }
+
+    //Nothing more arrives within the read timeout.
+    fn assert_idle(&mut self) {
+        assert!(!self.fill() && self.take_frame().is_none());
+    }
+}
+
+fn hello(player_id: u32, map_id: &str) -> String {
+    format!(
+        r#"{{"Hello":{{"protocol_version":5,"build_hash":"abc","map_id":"{}","player_id":{},"player_name":"Player {}","seed":{},"role":"Player"}}}}"#,
+        map_id, player_id, player_id, player_id
+    )
+}
+
+#[test]
+fn messages_are_relayed_within_a_room() {
+    let relay = RelayProcess::start();
+    let mut a = Client::connect(relay.port, "/12345");
+    let mut b = Client::connect(relay.port, "/12345");
+    let mut c = Client::connect(relay.port, "/other");
+    //Give the relay a moment to register every upgrade before messages start flowing.
+    std::thread::sleep(Duration::from_millis(100));
+
+    a.send_text("from a");
+    assert_eq!(b.receive_text().as_deref(), Some("from a"));
+    let frame = vec![0xEB; 1000];
+    b.send(0x2, &frame);
+    assert_eq!(a.receive(), Some((0x2, frame)));
+    a.assert_idle();
+    c.assert_idle();
+
+    //Pings are answered by the relay itself.
+    c.send(0x9, b"are you there");
+    assert_eq!(c.receive(), Some((0xA, b"are you there".to_vec())));
+    b.assert_idle();
+
+    //The relay answers a close and the room carries on without b.
+    b.send(0x8, &[]);
+    assert_eq!(b.receive().map(|(opcode, _)| opcode), Some(0x8));
+    let mut d = Client::connect(relay.port, "/12345");
+    std::thread::sleep(Duration::from_millis(100));
+    a.send_text("welcome d");
+    assert_eq!(d.receive_text().as_deref(), Some("welcome d"));
+}
+
+#[test]
+fn late_arrivals_get_the_room_hellos_and_bad_hellos_are_rejected() {
+    let relay = RelayProcess::start();
+    let mut a = Client::connect(relay.port, "/12345");
+    std::thread::sleep(Duration::from_millis(100));
+    a.send_text(&hello(1, "map"));
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut b = Client::connect(relay.port, "/12345");
+    std::thread::sleep(Duration::from_millis(100));
+    b.send_text(&hello(2, "other_map"));
+    assert_eq!(b.receive_text().as_deref(), Some(r#"{"HelloRejected":{"player_id":2,"rejection":{"MapMismatch":{"local":"map","remote":"other_map"}}}}"#));
+    a.assert_idle();
+
+    b.send_text(&hello(2, "map"));
+    assert_eq!(b.receive_text(), Some(hello(1, "map")));
+    assert_eq!(a.receive_text(), Some(hello(2, "map")));
+}
+
+//Sends something the relay must reject.
+type Violation = fn(&mut Client);
+
+#[test]
+fn protocol_violations_close_the_connection() {
+    let relay = RelayProcess::start();
+    let violations: [(Violation, u16); 3] = [
+        (|client| client.send_frame(0x09, b"ping"), 1002),
+        (|client| client.send(0x8, &[b'x'; 126]), 1002),
+        (|client| client.send(0x1, &[b'a', 0xFF, 0xFE]), 1007),
+    ];
+    for (violate, status) in violations.iter() {
+        let mut client = Client::connect(relay.port, "/12345");
+        violate(&mut client);
+        assert_eq!(client.receive(), Some((0x8, status.to_be_bytes().to_vec())));
+        assert!(!client.fill() && client.take_frame().is_none());
+    }
+}